@@ -0,0 +1,14 @@
+# Source this from .bashrc to cd into hunter's last
+# directory after quitting with Q. The selected file is left in $F and
+# the selected files in the array $MF.
+function hunter() {
+	local exit_file
+	exit_file="$(mktemp "${TMPDIR:-/tmp}/hunter_cwd.XXXXXX")" || return
+	trap 'rm -f -- "$exit_file"; trap - RETURN' RETURN
+	command hunter --exit-file="$exit_file" --exit-format=bash "$@"
+	# hunter only writes it on Q, anything else wasn't written by us
+	if [ -s "$exit_file" ] && [ -O "$exit_file" ]; then
+		source "$exit_file"
+		cd -- "$HUNTER_CWD"
+	fi
+}
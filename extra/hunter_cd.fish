@@ -0,0 +1,21 @@
+# Put this in ~/.config/fish/functions/hunter.fish to cd into hunter's
+# last directory after quitting with Q. The selected file is left in
+# $F and the selected files in the list $MF.
+function hunter
+	set -l tmp_dir /tmp
+	if set -q XDG_RUNTIME_DIR
+		set tmp_dir $XDG_RUNTIME_DIR
+	else if set -q TMPDIR
+		set tmp_dir $TMPDIR
+	end
+	set -l exit_file (mktemp $tmp_dir/hunter_cwd.XXXXXX); or return
+
+	command hunter --exit-file=$exit_file --exit-format=fish $argv
+	# hunter only writes it on Q, anything else wasn't written by us
+	if test -s $exit_file; and test -O $exit_file
+		source $exit_file
+		cd -- $HUNTER_CWD
+	end
+	# fish has no traps, but nothing above can skip this
+	rm -f -- $exit_file
+end
@@ -0,0 +1,220 @@
+use std::path::PathBuf;
+
+use crate::shell::ExitFormat;
+
+
+lazy_static! {
+    // Parsed once at startup, before the terminal is set up, so
+    // errors can still be printed normally
+    pub static ref ARGS: Args = Args::from_env();
+}
+
+const USAGE: &str = "\
+Usage: hunter [OPTIONS] [PATH]
+
+Starts in PATH if it's a directory, or with PATH selected if it's a file.
+
+Options:
+    -a, --show-hidden      show hidden files
+        --no-show-hidden   don't show hidden files
+        --animation        enable animations
+        --no-animation     disable animations
+    -i, --icons            show icons
+        --no-icons         don't show icons
+    -c, --config FILE      read config from FILE
+    -s, --session NAME     restore and save the session NAME
+        --choosefile FILE  write the opened file to FILE and quit
+        --choosefiles FILE write the selected files to FILE when a file is opened
+        --choosedir FILE   write the last directory to FILE on quit
+    -0, --null             separate chosen paths with NUL instead of newlines
+        --exit-file FILE   where Q writes the directory and selections
+        --exit-format FMT  format of the exit file: bash, fish or nul
+        --remote CMD...    send CMD to a running hunter and print the reply,
+                           all arguments after --remote belong to CMD
+        --socket SOCKET    socket of the hunter to send remote commands to
+    -h, --help             show this help
+    -V, --version          show the version
+    --                     treat the next argument as PATH, even if it starts with -";
+
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    pub path: Option<PathBuf>,
+    // Unset unless given, so the config decides
+    pub show_hidden: Option<bool>,
+    pub animation: Option<bool>,
+    pub icons: Option<bool>,
+    pub config: Option<PathBuf>,
+    pub session: Option<String>,
+    pub choosefile: Option<PathBuf>,
+    pub choosefiles: Option<PathBuf>,
+    pub choosedir: Option<PathBuf>,
+    pub null: bool,
+    pub exit_file: Option<PathBuf>,
+    pub exit_format: Option<ExitFormat>,
+    pub remote: Option<Vec<String>>,
+    pub socket: Option<PathBuf>,
+}
+
+impl Args {
+    fn from_env() -> Args {
+        match Args::parse(std::env::args().skip(1)) {
+            Ok(args) => args,
+            Err(err) => {
+                eprintln!("hunter: {}\n\n{}", err, USAGE);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    fn parse<I: Iterator<Item=String>>(mut input: I) -> Result<Args, String> {
+        let mut args = Args::default();
+
+        while let Some(arg) = input.next() {
+            // Allow --option=value as well as --option value
+            let (arg, inline_value) = match arg.find("=") {
+                Some(pos) if arg.starts_with("--") => {
+                    (arg[..pos].to_string(), Some(arg[pos+1..].to_string()))
+                }
+                _ => (arg, None)
+            };
+
+            let mut value = |name: &str| {
+                inline_value.clone()
+                    .or_else(|| input.next())
+                    .ok_or(format!("{} needs a value", name))
+            };
+
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                "-V" | "--version" => {
+                    println!("hunter {}", env!("CARGO_PKG_VERSION"));
+                    std::process::exit(0);
+                }
+                "-a" | "--show-hidden" => args.show_hidden = Some(true),
+                "--no-show-hidden" => args.show_hidden = Some(false),
+                "--animation" => args.animation = Some(true),
+                "--no-animation" => args.animation = Some(false),
+                "-i" | "--icons" => args.icons = Some(true),
+                "--no-icons" => args.icons = Some(false),
+                "-c" | "--config" => args.config = Some(PathBuf::from(value(&arg)?)),
+                "-s" | "--session" => args.session = Some(value(&arg)?),
+                "--choosefile" => args.choosefile = Some(PathBuf::from(value(&arg)?)),
+                "--choosefiles" => args.choosefiles = Some(PathBuf::from(value(&arg)?)),
+                "--choosedir" => args.choosedir = Some(PathBuf::from(value(&arg)?)),
+                "-0" | "--null" => args.null = true,
+                "--exit-file" => args.exit_file = Some(PathBuf::from(value(&arg)?)),
+                "--exit-format" => {
+                    let format = value(&arg)?;
+                    let format = format.parse()
+                        .map_err(|_| format!("unknown exit format: {}", format))?;
+                    args.exit_format = Some(format);
+                }
+                "--socket" => args.socket = Some(PathBuf::from(value(&arg)?)),
+                // Everything after --remote is the command, so it has to
+                // come last
+                "--remote" => {
+                    let command = inline_value.clone()
+                        .into_iter()
+                        .chain(input.by_ref())
+                        .collect::<Vec<String>>();
+                    if command.is_empty() {
+                        return Err(format!("{} needs a command", arg));
+                    }
+                    args.remote = Some(command);
+                }
+                "--" => {
+                    for arg in input.by_ref() {
+                        args.set_path(arg)?;
+                    }
+                }
+                _ if arg.starts_with("-") && arg != "-" => {
+                    return Err(format!("unknown option: {}", arg));
+                }
+                _ => args.set_path(arg)?
+            }
+        }
+
+        Ok(args)
+    }
+
+    fn set_path(&mut self, arg: String) -> Result<(), String> {
+        if self.path.is_some() {
+            return Err(format!("unexpected argument: {}", arg));
+        }
+        self.path = Some(PathBuf::from(arg));
+        Ok(())
+    }
+
+    pub fn choosing_files(&self) -> bool {
+        self.choosefile.is_some() || self.choosefiles.is_some()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::Args;
+    use std::path::PathBuf;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn boolean_options_and_their_no_forms() {
+        let args = parse(&["-a", "--no-animation", "--icons"]).unwrap();
+        assert_eq!(args.show_hidden, Some(true));
+        assert_eq!(args.animation, Some(false));
+        assert_eq!(args.icons, Some(true));
+
+        // The last one wins
+        let args = parse(&["--show-hidden", "--no-show-hidden", "--no-icons"]).unwrap();
+        assert_eq!(args.show_hidden, Some(false));
+        assert_eq!(args.animation, None);
+        assert_eq!(args.icons, Some(false));
+    }
+
+    #[test]
+    fn config_takes_a_value() {
+        let args = parse(&["--config", "/tmp/config"]).unwrap();
+        assert_eq!(args.config, Some(PathBuf::from("/tmp/config")));
+
+        let args = parse(&["--config=/tmp/other", "-c", "/tmp/config"]).unwrap();
+        assert_eq!(args.config, Some(PathBuf::from("/tmp/config")));
+
+        assert!(parse(&["--config"]).is_err());
+    }
+
+    #[test]
+    fn remote_takes_the_remaining_arguments() {
+        let args = parse(&["--socket", "/tmp/sock", "--remote", "cd", "-a", "/tmp"]).unwrap();
+        assert_eq!(args.socket, Some(PathBuf::from("/tmp/sock")));
+        assert_eq!(args.remote, Some(vec!["cd".to_string(),
+                                          "-a".to_string(),
+                                          "/tmp".to_string()]));
+        assert_eq!(args.show_hidden, None);
+
+        let args = parse(&["--remote=select", "foo"]).unwrap();
+        assert_eq!(args.remote, Some(vec!["select".to_string(), "foo".to_string()]));
+
+        assert!(parse(&["--remote"]).is_err());
+    }
+
+    #[test]
+    fn path_after_double_dash() {
+        let args = parse(&["-a", "--", "-weird-dir"]).unwrap();
+        assert_eq!(args.path, Some(PathBuf::from("-weird-dir")));
+        assert_eq!(args.show_hidden, Some(true));
+
+        let args = parse(&["--", "--remote"]).unwrap();
+        assert_eq!(args.path, Some(PathBuf::from("--remote")));
+        assert_eq!(args.remote, None);
+
+        assert!(parse(&["--", "foo", "bar"]).is_err());
+        assert!(parse(&["foo", "--", "bar"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
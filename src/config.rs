@@ -1,4 +1,13 @@
 use crate::paths;
+use crate::fuzzy::MatchMode;
+use crate::files::SortKey;
+use crate::shell::ExitFormat;
+use crate::hooks::Hooks;
+use crate::imgview::Graphics;
+use crate::opener::Opener;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use crate::fail::{HError, HResult, ErrorLog};
 
 #[derive(Debug, Clone)]
@@ -7,7 +16,22 @@ pub struct Config {
     pub show_hidden: bool,
     pub select_cmd: String,
     pub cd_cmd: String,
-    pub icons: bool
+    pub icons: bool,
+    pub flat_depth: usize,
+    pub match_mode: MatchMode,
+    pub smart_case: bool,
+    pub sort_chain: Vec<SortKey>,
+    pub prune_dir_settings: bool,
+    pub restore_session: bool,
+    pub exit_file: Option<PathBuf>,
+    pub exit_format: ExitFormat,
+    pub hooks: Hooks,
+    pub opener_file: Option<PathBuf>,
+    // Shared by all copies of the config, see FileBrowser::opener
+    pub opener: Arc<Mutex<Opener>>,
+    pub syntax_highlight: bool,
+    pub syntax_theme: String,
+    pub image_preview: Option<Graphics>
 }
 
 
@@ -18,7 +42,21 @@ impl Config {
             show_hidden: false,
             select_cmd: "find -type f | fzf -m".to_string(),
             cd_cmd: "find -type d | fzf".to_string(),
-            icons: false
+            icons: false,
+            flat_depth: 5,
+            match_mode: MatchMode::Substring,
+            smart_case: false,
+            sort_chain: vec![],
+            prune_dir_settings: true,
+            restore_session: false,
+            exit_file: None,
+            exit_format: ExitFormat::Bash,
+            hooks: Hooks::default(),
+            opener_file: None,
+            opener: Arc::new(Mutex::new(Opener::new())),
+            syntax_highlight: true,
+            syntax_theme: crate::highlight::DEFAULT_THEME.to_string(),
+            image_preview: Some(Graphics::detect())
         }
     }
 
@@ -26,7 +64,7 @@ impl Config {
         let config_path = paths::config_path()?;
 
         if !config_path.exists() {
-            return Ok(Config::new());
+            return Ok(Config::new().with_args());
         }
 
         let config_string = std::fs::read_to_string(config_path)?;
@@ -39,6 +77,14 @@ impl Config {
                 Ok(("show_hidden", "off")) => { config.show_hidden = false; },
                 Ok(("icons", "on")) => config.icons = true,
                 Ok(("icons", "off")) => config.icons = false,
+                Ok(("prune_dir_settings", "on")) => config.prune_dir_settings = true,
+                Ok(("prune_dir_settings", "off")) => config.prune_dir_settings = false,
+                Ok(("restore_session", "on")) => config.restore_session = true,
+                Ok(("restore_session", "off")) => config.restore_session = false,
+                Ok(("syntax_highlight", "on")) => config.syntax_highlight = true,
+                Ok(("syntax_highlight", "off")) => config.syntax_highlight = false,
+                // Checked when it's first used, see highlight::highlight_file
+                Ok(("syntax_theme", theme)) => config.syntax_theme = theme.to_string(),
                 Ok(("select_cmd", cmd)) => {
                     let cmd = cmd.to_string();
                     config.select_cmd = cmd;
@@ -47,15 +93,67 @@ impl Config {
                     let cmd = cmd.to_string();
                     config.cd_cmd = cmd;
                 }
+                Ok(("match_mode", "substring")) => config.match_mode = MatchMode::Substring,
+                Ok(("match_mode", "fuzzy")) => config.match_mode = MatchMode::Fuzzy,
+                Ok(("smart_case", "on")) => config.smart_case = true,
+                Ok(("smart_case", "off")) => config.smart_case = false,
+                Ok(("sort_chain", chain)) => {
+                    match SortKey::parse_chain(chain) {
+                        Ok(chain) => config.sort_chain = chain,
+                        Err(_) => HError::config_error::<Config>(line.to_string()).log()
+                    }
+                }
+                Ok(("exit_file", path)) => {
+                    config.exit_file = Some(paths::expand_home(path))
+                }
+                Ok(("opener_file", path)) => {
+                    config.opener_file = Some(paths::expand_home(path))
+                }
+                Ok(("exit_format", format)) => {
+                    match format.parse() {
+                        Ok(format) => config.exit_format = format,
+                        Err(_) => HError::config_error::<Config>(line.to_string()).log()
+                    }
+                }
+                Ok(("image_preview", "off")) => config.image_preview = None,
+                Ok(("image_preview", graphics)) => {
+                    match graphics.parse() {
+                        Ok(graphics) => config.image_preview = Some(graphics),
+                        Err(_) => HError::config_error::<Config>(line.to_string()).log()
+                    }
+                }
+                Ok(("flat_depth", depth)) => {
+                    match depth.parse() {
+                        Ok(depth) => config.flat_depth = depth,
+                        Err(_) => HError::config_error::<Config>(line.to_string()).log()
+                    }
+                }
+                Ok((key, cmd)) if key.starts_with("hook_") => {
+                    match key["hook_".len()..].parse() {
+                        Ok(hook) => config.hooks.set(hook, cmd),
+                        Err(_) => HError::config_error::<Config>(line.to_string()).log()
+                    }
+                }
                 _ => { HError::config_error::<Config>(line.to_string()).log(); }
             }
             config
         });
-        Ok(config)
+
+        Ok(config.with_args())
+    }
+
+    // Command line options win over the config file
+    fn with_args(mut self) -> Config {
+        let args = &crate::args::ARGS;
+        if let Some(show_hidden) = args.show_hidden { self.show_hidden = show_hidden; }
+        if let Some(animation) = args.animation { self.animation = animation; }
+        if let Some(icons) = args.icons { self.icons = icons; }
+        self
     }
 
     fn prep_line<'a>(line: &'a str) -> HResult<(&'a str, &'a str)> {
-        let setting = line.split("=").collect::<Vec<&str>>();
+        // Commands can contain = themselves
+        let setting = line.splitn(2, "=").collect::<Vec<&str>>();
         if setting.len() == 2 {
             Ok((setting[0], setting[1]))
         } else {
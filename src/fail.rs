@@ -97,6 +97,18 @@ pub enum HError {
     ConfigLineError(String),
     #[fail(display = "New input in Minibuffer")]
     MiniBufferInputUpdated(String),
+    #[fail(display = "Selection moved in Minibuffer")]
+    MiniBufferSelectionMoved(isize),
+    #[fail(display = "Invalid filter: {}", _0)]
+    FilterError(String),
+    #[fail(display = "Remote command failed: {}", _0)]
+    RemoteError(String),
+    #[fail(display = "Script error: {}", _0)]
+    ScriptError(String),
+    #[fail(display = "Plugin {} failed: {}", plugin, msg)]
+    PluginError { plugin: String, msg: String },
+    #[fail(display = "Image error: {}", _0)]
+    ImageError(String),
 }
 
 impl HError {
@@ -171,6 +183,19 @@ impl HError {
         Err(HError::MiniBufferInputUpdated(input))
     }
 
+    pub fn selection_moved<T>(amount: isize) -> HResult<T> {
+        Err(HError::MiniBufferSelectionMoved(amount))
+    }
+
+    pub fn remote<T>(msg: &str) -> HResult<T> {
+        Err(HError::RemoteError(msg.to_string()))
+    }
+
+    pub fn plugin<T>(plugin: &str, msg: &str) -> HResult<T> {
+        Err(HError::PluginError { plugin: plugin.to_string(),
+                                  msg: msg.to_string() })
+    }
+
 
 }
 
@@ -301,6 +326,35 @@ impl From<notify::Error> for HError {
     }
 }
 
+impl From<regex::Error> for HError {
+    fn from(error: regex::Error) -> Self {
+        let err = HError::FilterError(format!("{}", error));
+        err
+    }
+}
+
+impl From<rlua::Error> for HError {
+    fn from(error: rlua::Error) -> Self {
+        let err = HError::ScriptError(format!("{}", error));
+        err
+    }
+}
+
+impl From<image::ImageError> for HError {
+    fn from(error: image::ImageError) -> Self {
+        let err = HError::ImageError(format!("{}", error));
+        err
+    }
+}
+
+impl From<serde_json::Error> for HError {
+    fn from(error: serde_json::Error) -> Self {
+        let err = HError::PluginError { plugin: String::from("?"),
+                                        msg: format!("Invalid JSON: {}", error) };
+        err
+    }
+}
+
 impl From<async_value::AError> for HError {
     fn from(error: async_value::AError) -> Self {
         let err = HError::AError(error,
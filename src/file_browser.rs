@@ -1,14 +1,13 @@
 use termion::event::Key;
 use pathbuftools::PathBufTools;
 use osstrtools::OsStrTools;
-use async_value::Stale;
+use async_value::{Async, Stale};
 
-use std::io::Write;
 use std::sync::{Arc, Mutex, RwLock};
 use std::path::PathBuf;
 use std::ffi::OsString;
 use std::os::unix::ffi::OsStringExt;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use crate::files::{File, Files};
 use crate::fscache::FsCache;
@@ -16,6 +15,8 @@ use crate::listview::ListView;
 use crate::hbox::HBox;
 use crate::widget::Widget;
 use crate::tabview::{TabView, Tabbable};
+use crate::split::SplitView;
+use crate::finder::Finder;
 use crate::preview::{Previewer, AsyncWidget};
 use crate::textview::TextView;
 use crate::fail::{HResult, HError, ErrorLog};
@@ -28,6 +29,14 @@ use crate::foldview::LogView;
 use crate::coordinates::Coordinates;
 use crate::dirty::Dirtyable;
 use crate::stats::{FsStat, FsExt};
+use crate::remote::{self, RemoteCommand};
+use crate::hooks::Hook;
+use crate::script::{Scripts, SharedScripts};
+use crate::plugin::{PLUGINS, Plugin, Action};
+use crate::opener::{Opener, OpenWithPopup, Rule, OpenMode};
+use crate::hexview::HexView;
+
+use serde_json::Value;
 
 #[derive(PartialEq)]
 pub enum FileBrowserWidgets {
@@ -83,27 +92,18 @@ pub struct FileBrowser {
     bookmarks: Arc<Mutex<BMPopup>>,
     log_view: Arc<Mutex<LogView>>,
     fs_cache: FsCache,
-    fs_stat: Arc<RwLock<FsStat>>
+    fs_stat: Arc<RwLock<FsStat>>,
+    scripts: SharedScripts,
+    // Directories being loaded to expand them in the tree
+    pending_folds: Vec<Async<Files>>
 }
 
-impl Tabbable for TabView<FileBrowser> {
+impl Tabbable for TabView<SplitView> {
     fn new_tab(&mut self) -> HResult<()> {
-        let cur_tab = self.active_tab_();
-
-        let settings = cur_tab.fs_cache.tab_settings.read()?.clone();
-        let cache = cur_tab.fs_cache.new_client(settings).ok();
-
-        let mut tab = FileBrowser::new(&self.active_tab_().core, cache)?;
-
-        let proc_view = cur_tab.proc_view.clone();
-        let bookmarks = cur_tab.bookmarks.clone();
-        let log_view  = cur_tab.log_view.clone();
-        tab.proc_view = proc_view;
-        tab.bookmarks = bookmarks;
-        tab.log_view  = log_view;
-        tab.fs_stat = cur_tab.fs_stat.clone();
+        let core = self.get_core()?.clone();
+        let tab = self.active_tab_().browser()?.new_linked(&core)?;
 
-        self.push_widget(tab)?;
+        self.push_widget(SplitView::new(&core, tab))?;
         self.active = self.widgets.len() - 1;
         Ok(())
     }
@@ -123,9 +123,9 @@ impl Tabbable for TabView<FileBrowser> {
     }
 
     fn get_tab_names(&self) -> Vec<Option<String>> {
-        self.widgets.iter().map(|filebrowser| {
-            let path = filebrowser.cwd.path();
-            let last_dir = path.components().last().unwrap();
+        self.widgets.iter().map(|tab| {
+            let path = tab.browser().ok()?.cwd.path();
+            let last_dir = path.components().last()?;
             let dir_name = last_dir.as_os_str().to_string_lossy().to_string();
             Some(dir_name)
         }).collect()
@@ -140,22 +140,27 @@ impl Tabbable for TabView<FileBrowser> {
     }
 
     fn on_tab_switch(&mut self) -> HResult<()> {
+        self.active_tab_().browser()?.run_hook(Hook::TabSwitch, vec![]);
         self.active_tab_mut().refresh()
     }
 
     fn on_key_sub(&mut self, key: Key) -> HResult<()> {
         match key {
             Key::Char('!') => {
-                let tab_dirs = self.widgets.iter().map(|w| w.cwd.clone())
-                                                  .collect::<Vec<_>>();
+                let tab_dirs = self.widgets.iter()
+                                           .filter_map(|w| w.browser().ok())
+                                           .map(|w| w.cwd.clone())
+                                           .collect::<Vec<_>>();
                 let selected_files = self
                     .widgets
                     .iter()
+                    .filter_map(|w| w.browser().ok())
                     .map(|w| {
                         w.selected_files().unwrap_or(vec![])
                     }).collect();
 
-                self.widgets[self.active].exec_cmd(tab_dirs, selected_files)
+                self.widgets[self.active].browser_mut()?.exec_cmd(tab_dirs,
+                                                                  selected_files)
             }
             _ => { self.active_tab_mut().on_key(key) }
         }
@@ -163,13 +168,14 @@ impl Tabbable for TabView<FileBrowser> {
 
     fn on_refresh(&mut self) -> HResult<()> {
         let fs_changes = self.active_tab_()
+            .browser()?
             .fs_cache
             .fs_changes
             .write()?
             .drain(..)
             .collect::<Vec<_>>();
 
-        for tab in &mut self.widgets {
+        for tab in self.widgets.iter_mut().flat_map(|w| w.browsers_mut()) {
             for (dir, old_file, new_file) in fs_changes.iter() {
                 tab.replace_file(&dir,
                                  old_file.as_ref(),
@@ -179,6 +185,7 @@ impl Tabbable for TabView<FileBrowser> {
 
         let open_dirs = self.widgets
             .iter()
+            .flat_map(|w| w.browsers())
             .fold(HashSet::new(), |mut dirs, tab| {
                 tab.left_dir().map(|dir| dirs.insert(dir.clone())).ok();
                 dirs.insert(tab.cwd.clone());
@@ -191,20 +198,25 @@ impl Tabbable for TabView<FileBrowser> {
                 dirs
             });
 
-        self.active_tab_mut_().fs_cache.watch_only(open_dirs).log();
-        self.active_tab_mut_().fs_stat.write()?.refresh().log();
+        self.active_tab_mut_().browser_mut()?.fs_cache.watch_only(open_dirs).log();
+        self.active_tab_mut_().browser_mut()?.fs_stat.write()?.refresh().log();
         Ok(())
     }
 
     fn on_config_loaded(&mut self) -> HResult<()> {
         let show_hidden = self.config().show_hidden();
+        let match_mode = self.config().match_mode;
+        let smart_case = self.config().smart_case;
+        let sort_chain = self.config().sort_chain.clone();
 
-        for tab in self.widgets.iter_mut() {
+        for tab in self.widgets.iter_mut().flat_map(|w| w.browsers_mut()) {
             tab.left_async_widget_mut().map(|async_w| {
                 async_w.widget.on_ready(move |mut w, _| {
                     w.as_mut()
                         .map(|mut w| {
                             w.content.show_hidden = show_hidden;
+                            w.content.match_mode = match_mode;
+                            w.content.smart_case = smart_case;
                             w.content.dirty_meta.set_dirty();
                             w.refresh().log();
                         }).ok();
@@ -212,11 +224,17 @@ impl Tabbable for TabView<FileBrowser> {
                 }).log();
             }).log();
 
+            let sort_chain = sort_chain.clone();
             tab.main_async_widget_mut().map(|async_w| {
                 async_w.widget.on_ready(move |mut w, _| {
                     w.as_mut()
                         .map(|mut w| {
                             w.content.show_hidden = show_hidden;
+                            w.content.match_mode = match_mode;
+                            w.content.smart_case = smart_case;
+                            if w.content.sort_chain.is_none() {
+                                w.content.sort_chain = Some(sort_chain.clone());
+                            }
                             w.content.dirty_meta.set_dirty();
                             w.content.sort();
                             w.refresh().log();
@@ -229,6 +247,61 @@ impl Tabbable for TabView<FileBrowser> {
         }
         Ok(())
     }
+
+    fn on_remote(&mut self, command: RemoteCommand) -> HResult<String> {
+        match command {
+            RemoteCommand::OpenTab(path) => {
+                self.new_tab()?;
+                if let Some(path) = path {
+                    self.active_tab_mut_().browser_mut()?.goto_path(&path)?;
+                }
+                Ok(remote::ok_reply())
+            }
+            RemoteCommand::Action(action) => {
+                let key = RemoteCommand::action_key(&action)?;
+                Tabbable::on_key(self, key)?;
+                Ok(remote::ok_reply())
+            }
+            command => self.active_tab_mut().on_remote(command)
+        }
+    }
+
+    fn on_quit(&mut self) -> HResult<()> {
+        let prune = self.config().prune_dir_settings;
+        let active = self.active;
+        let mut settings = HashMap::new();
+
+        // Each tab has its own settings, the active tab's win
+        let mut tabs = self.widgets.iter_mut().enumerate().collect::<Vec<_>>();
+        tabs.sort_by_key(|(i, _)| *i == active);
+
+        for (_, tab) in tabs {
+            for browser in tab.browsers_mut() {
+                browser.cache_files().log();
+                settings.extend(browser.fs_cache.tab_settings.read()?.clone());
+            }
+        }
+
+        self.active_tab_().browser()?.run_hook(Hook::Quit, vec![]);
+
+        if let Some(target) = &crate::args::ARGS.choosedir {
+            let cwd = self.active_tab_().browser()?.cwd.path.clone();
+            write_chosen_paths(target, &[cwd], crate::args::ARGS.null).log();
+        }
+
+        PLUGINS.stop();
+
+        FsCache::save_dir_settings(&settings, prune)
+    }
+}
+
+// Paths are written as raw bytes, so any file name works
+pub fn write_chosen_paths(target: &std::path::Path,
+                          paths: &[PathBuf],
+                          null: bool) -> HResult<()> {
+    let separator = if null { b'\0' } else { b'\n' };
+    std::fs::write(target, crate::shell::join_paths(paths, separator))?;
+    Ok(())
 }
 
 
@@ -238,10 +311,51 @@ impl Tabbable for TabView<FileBrowser> {
 
 
 impl FileBrowser {
-    pub fn new(core: &WidgetCore, cache: Option<FsCache>) -> HResult<FileBrowser> {
-        let fs_cache = cache.unwrap_or_else(|| FsCache::new(core.get_sender()));
+    // Creates a FileBrowser sharing processes, bookmarks, etc. with this one
+    pub fn new_linked(&self, core: &WidgetCore) -> HResult<FileBrowser> {
+        let settings = self.fs_cache.tab_settings.read()?.clone();
+        let cache = self.fs_cache.new_client(settings).ok();
+
+        let mut browser = FileBrowser::new(core, cache, Some(self.scripts()))?;
+        browser.link_to(self);
+        Ok(browser)
+    }
 
+    pub fn link_to(&mut self, other: &FileBrowser) {
+        self.proc_view = other.proc_view.clone();
+        self.bookmarks = other.bookmarks.clone();
+        self.log_view  = other.log_view.clone();
+        self.fs_stat = other.fs_stat.clone();
+        self.scripts = other.scripts.clone();
+    }
+
+    pub fn fs_cache(&self) -> &FsCache {
+        &self.fs_cache
+    }
+
+    pub fn proc_view(&self) -> Arc<Mutex<ProcView>> {
+        self.proc_view.clone()
+    }
+
+    pub fn scripts(&self) -> SharedScripts {
+        self.scripts.clone()
+    }
+
+    pub fn new(core: &WidgetCore,
+               cache: Option<FsCache>,
+               scripts: Option<SharedScripts>) -> HResult<FileBrowser> {
         let cwd = std::env::current_dir().unwrap();
+        FileBrowser::new_in(core, cache, scripts, cwd)
+    }
+
+    // Scripts are only loaded if none are passed in, so linked browsers
+    // don't run them again
+    pub fn new_in(core: &WidgetCore,
+                  cache: Option<FsCache>,
+                  scripts: Option<SharedScripts>,
+                  cwd: PathBuf) -> HResult<FileBrowser> {
+        let fs_cache = cache.unwrap_or_else(|| FsCache::new(core.get_sender()));
+
         let mut core_m = core.clone();
         let mut core_l = core.clone();
         let mut core_p = core.clone();
@@ -340,6 +454,7 @@ impl FileBrowser {
         let bookmarks = BMPopup::new(&core);
         let log_view = LogView::new(&core, vec![]);
         let fs_stat = FsStat::new().unwrap();
+        let scripts = scripts.unwrap_or_else(Scripts::load_shared);
 
 
 
@@ -351,7 +466,9 @@ impl FileBrowser {
                          bookmarks: Arc::new(Mutex::new(bookmarks)),
                          log_view: Arc::new(Mutex::new(log_view)),
                          fs_cache: fs_cache,
-                         fs_stat: Arc::new(RwLock::new(fs_stat))
+                         fs_stat: Arc::new(RwLock::new(fs_stat)),
+                         scripts: scripts,
+                         pending_folds: vec![]
         })
     }
 
@@ -403,30 +520,155 @@ impl FileBrowser {
 
             let main_widget = FileBrowserWidgets::FileList(main_widget);
             self.columns.insert_widget(1, main_widget);
+            self.run_hook(Hook::Cd, vec![]);
+
+        } else if crate::args::ARGS.choosing_files() {
+            self.choose_files()?;
+        } else {
+            let files = self.files_to_open()?;
+            self.open_files(files)?;
+        }
+        Ok(())
+    }
+
+    // The multi selection if there is one, otherwise the selected file
+    fn files_to_open(&self) -> HResult<Vec<File>> {
+        let selected_files = self.selected_files()?
+            .into_iter()
+            .filter(|file| !file.is_dir())
+            .collect::<Vec<File>>();
 
+        if selected_files.is_empty() {
+            Ok(vec![self.selected_file()?])
         } else {
-            self.preview_widget().map(|preview| preview.cancel_animation()).log();
-            self.core.get_sender().send(Events::InputEnabled(false))?;
-            self.core.screen.suspend().log();
-
-            let status = std::process::Command::new("rifle")
-                .args(file.path.file_name())
-                .status();
-
-            self.core.screen.activate().log();
-            self.clear().log();
-
-            self.core.get_sender().send(Events::InputEnabled(true))?;
-
-            match status {
-                Ok(status) =>
-                    self.show_status(&format!("\"{}\" exited with {}",
-                                              "rifle", status)).log(),
-                Err(err) =>
-                    self.show_status(&format!("Can't run this \"{}\": {}",
-                                              "rifle", err)).log()
+            Ok(selected_files)
+        }
+    }
+
+    // Files that are handled by the same command are opened together
+    pub fn open_files(&mut self, files: Vec<File>) -> HResult<()> {
+        let opener = self.opener()?;
+        let mut groups: Vec<(Rule, Vec<File>)> = vec![];
+
+        for file in files {
+            let rule = opener.rule_for(&file)?;
+            match groups.iter_mut().find(|(group_rule, _)| {
+                group_rule.cmd == rule.cmd && group_rule.mode == rule.mode
+            }) {
+                Some((_, group)) => group.push(file),
+                None => groups.push((rule, vec![file]))
             }
         }
+
+        for (rule, files) in groups {
+            self.open_with(&rule, files).log();
+        }
+        Ok(())
+    }
+
+    // Read on first use and kept with the config until the file changes
+    pub fn opener(&self) -> HResult<Opener> {
+        let config = self.config();
+        let path = match &config.opener_file {
+            Some(path) => path.clone(),
+            None => crate::paths::opener_path()?
+        };
+
+        let mut opener = config.opener.lock()?;
+        opener.reload(&path)?;
+        Ok(opener.clone())
+    }
+
+    // Lets the user pick from everything that can open the selected file
+    pub fn open_with_menu(&mut self) -> HResult<()> {
+        if crate::args::ARGS.choosing_files() {
+            return self.choose_files();
+        }
+
+        let files = self.files_to_open()?;
+        let choices = self.opener()?.choices_for(&files);
+
+        self.preview_widget().map(|preview| preview.cancel_animation()).log();
+
+        loop {
+            let mut popup = OpenWithPopup::new(&self.core, choices.clone());
+            match popup.pick() {
+                Ok(rule) => return self.open_with(&rule, files),
+                Err(HError::TerminalResizedError) |
+                Err(HError::WidgetResizedError) => {
+                    self.core.screen.clear().log();
+                    self.resize().log();
+                    self.refresh().log();
+                    self.draw().log();
+                }
+                // Cancelled
+                Err(_) => return Ok(())
+            }
+        }
+    }
+
+    // The files are passed to the command as arguments, so it can use "$@"
+    pub fn open_with(&mut self, rule: &Rule, files: Vec<File>) -> HResult<()> {
+        self.run_hook(Hook::Open, files.clone());
+
+        let mut args = vec![OsString::from("-c"),
+                            OsString::from(&rule.cmd),
+                            OsString::from("sh")];
+        args.extend(files.iter().map(|file| file.path.clone().into_os_string()));
+
+        let names = files.iter()
+            .map(|file| file.name.clone())
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        match rule.mode {
+            OpenMode::Foreground => {
+                self.preview_widget().map(|preview| preview.cancel_animation()).log();
+                self.core.get_sender().send(Events::InputEnabled(false))?;
+                self.core.screen.suspend().log();
+
+                let status = std::process::Command::new("sh")
+                    .args(&args)
+                    .status();
+
+                self.core.screen.activate().log();
+                self.clear().log();
+
+                self.core.get_sender().send(Events::InputEnabled(true))?;
+
+                match status {
+                    Ok(status) =>
+                        self.show_status(&format!("\"{}\" exited with {}",
+                                                  rule.cmd, status)).log(),
+                    Err(err) =>
+                        self.show_status(&format!("Can't run this \"{}\": {}",
+                                                  rule.cmd, err)).log()
+                }
+            }
+            OpenMode::Background | OpenMode::Terminal => {
+                let (cmd, args) = if rule.mode == OpenMode::Terminal {
+                    let terminal = std::env::var("TERMINAL").unwrap_or("xterm".into());
+                    let mut term_args = vec![OsString::from("-e"), OsString::from("sh")];
+                    term_args.extend(args);
+                    (OsString::from(terminal), term_args)
+                } else {
+                    (OsString::from("sh"), args)
+                };
+
+                let cmd = crate::proclist::Cmd {
+                    cmd: cmd,
+                    short_cmd: Some(format!("{} {}", rule.cmd, names)),
+                    args: Some(args),
+                    cwd: self.cwd()?.clone(),
+                    cwd_files: None,
+                    tab_files: None,
+                    tab_paths: None
+                };
+
+                self.proc_view.lock()?.run_proc_raw(cmd)?;
+            }
+        }
+
         Ok(())
     }
 
@@ -474,6 +716,7 @@ impl FileBrowser {
             tab_paths: None
         };
 
+        self.run_hook(Hook::Open, vec![file.clone()]);
         self.proc_view.lock()?.run_proc_raw(cmd)?;
 
         Ok(())
@@ -494,6 +737,8 @@ impl FileBrowser {
 
     pub fn main_widget_goto(&mut self, dir: &File) -> HResult<()> {
         self.cache_files().log();
+        self.main_widget_mut().map(|w| w.content.stop_flat_view()).ok();
+        self.pending_folds.clear();
 
         let dir = dir.clone();
         let cache = self.fs_cache.clone();
@@ -525,6 +770,7 @@ impl FileBrowser {
             }).log();
         }
 
+        self.run_hook(Hook::Cd, vec![]);
         Ok(())
     }
 
@@ -556,6 +802,7 @@ impl FileBrowser {
         if let Ok(new_cwd) = self.cwd.parent_as_file() {
             let core = self.core.clone();
             let preview_files = self.take_main_files();
+            self.pending_folds.clear();
             let old_left = self.columns.remove_widget(0);
             self.prev_cwd = Some(self.cwd.clone());
             self.cwd = new_cwd.clone();
@@ -581,6 +828,7 @@ impl FileBrowser {
             }
             self.columns.replace_widget(1, old_left);
             self.main_widget_mut()?.content.meta_all();
+            self.run_hook(Hook::Cd, vec![]);
 
             if let Ok(preview_files) = preview_files {
                 self.preview_widget_mut().map(|preview| {
@@ -662,10 +910,34 @@ impl FileBrowser {
             }
         let file = self.selected_file()?.clone();
         let preview = self.preview_widget_mut()?;
+        let changed = preview.get_file() != Some(&file);
         preview.set_file(&file).log();
+
+        if changed {
+            self.config().hooks.run_debounced(Hook::Select, &self.cwd, vec![file]).log();
+        }
         Ok(())
     }
 
+    // Hooks get the cwd and files as $s, see hooks::Hooks::run
+    pub fn run_hook(&self, hook: Hook, files: Vec<File>) {
+        self.config().hooks.run(hook, &self.cwd, files, vec![]).log();
+    }
+
+    // The scripts are taken out while the binding runs, so the lock
+    // isn't held during the Lua call. Keys handled from inside it fall
+    // through to the built-in bindings.
+    fn run_script_binding(&mut self, key: Key) -> HResult<bool> {
+        let scripts = match self.scripts.lock()?.take() {
+            Some(scripts) => scripts,
+            None => return Ok(false)
+        };
+
+        let result = scripts.run_binding(key, self);
+        *self.scripts.lock()? = Some(scripts);
+        result
+    }
+
     pub fn set_left_selection(&mut self) -> HResult<()> {
         if self.cwd.parent().is_none() { return Ok(()) }
         if !self.left_async_widget_mut()?.ready() { return Ok(()) }
@@ -856,29 +1128,271 @@ impl FileBrowser {
         self.columns.toggle_zoom().log();
     }
 
+    pub fn toggle_tree_view(&mut self) -> HResult<()> {
+        if self.main_widget()?.content.flat_view.is_some() {
+            return self.show_status("Can't show tree in flat view!");
+        }
+
+        let main_widget = self.main_widget_mut()?;
+        let file = main_widget.clone_selected_file();
+
+        main_widget.content.toggle_tree_view();
+        main_widget.refresh()?;
+        main_widget.select_file(&file);
+
+        let tree_view = main_widget.content.tree_view;
+        self.show_status(&format!("Tree view: {}", tree_view))
+    }
+
+    pub fn toggle_flat_view(&mut self) -> HResult<()> {
+        // Also leaves grep results
+        if self.main_widget()?.content.flat_view.is_some() {
+            let cwd = self.cwd.clone();
+            self.main_widget_goto(&cwd)?;
+            return self.show_status("Flat view: false");
+        }
+
+        let depth = self.config().flat_depth;
+        let sender = self.core.get_sender();
+
+        let main_widget = self.main_widget_mut()?;
+        main_widget.content.start_flat_view(depth, sender);
+        main_widget.set_selection(0);
+        main_widget.refresh()?;
+
+        self.show_status(&format!("Flat view: true, depth: {}", depth))
+    }
+
+    pub fn select_grep_hits(&mut self) -> HResult<()> {
+        let main_widget = self.main_widget_mut()?;
+        let num = main_widget.content.select_grep_hits()?;
+        main_widget.set_selection(0);
+        main_widget.refresh()?;
+
+        self.show_status(&format!("Selected {} files", num))
+    }
+
+    pub fn toggle_fold(&mut self) -> HResult<()> {
+        if self.main_widget()?.content.flat_view.is_some() {
+            return self.show_status("Can't fold in flat view!");
+        }
+
+        let file = self.selected_file()?;
+        let expanded = self.main_widget()?.content.is_expanded(&file.path);
+
+        if expanded {
+            // Also works for placeholders, they have their directory's path
+            self.main_widget_mut()?.content.collapse_dir(&file.path);
+        } else if file.is_dir() {
+            // Expanded once it's loaded, see expand_pending_folds
+            self.load_fold(&file);
+            return Ok(());
+        } else if file.parent()? != self.cwd.path {
+            // Fold the directory containing this file and jump to it
+            let parent = file.parent_as_file()?;
+            let main_widget = self.main_widget_mut()?;
+            main_widget.content.collapse_dir(&parent.path);
+            main_widget.refresh()?;
+            main_widget.select_file(&parent);
+            return Ok(());
+        } else {
+            return Ok(());
+        }
+
+        let main_widget = self.main_widget_mut()?;
+        main_widget.refresh()?;
+        main_widget.select_file(&file);
+        Ok(())
+    }
+
+    fn load_fold(&mut self, dir: &File) {
+        let cache = self.fs_cache.clone();
+        let dir = dir.clone();
+        let sender = Mutex::new(self.core.get_sender());
+
+        let mut files = Async::new(move |stale: &Stale| {
+            let files = cache.get_files(&dir, stale.clone())?.1.run_sync()?;
+            let mut files = FsCache::ensure_not_empty(files)?;
+            files.meta_all_sync().log();
+            Ok(files)
+        });
+
+        files.on_ready(move |_, _| {
+            sender.lock().map(|s| s.send(Events::WidgetReady)).ok();
+            Ok(())
+        }).log();
+        files.run().log();
+
+        self.pending_folds.push(files);
+    }
+
+    fn expand_pending_folds(&mut self) -> HResult<()> {
+        if self.pending_folds.is_empty() { return Ok(()); }
+
+        let (ready, pending): (Vec<_>, Vec<_>) = self.pending_folds
+            .drain(..)
+            .partition(|files| files.is_ready());
+        self.pending_folds = pending;
+
+        for mut files in ready {
+            files.pull_async()?;
+            let files = files.value?;
+            let selected = self.selected_file()?;
+
+            let main_widget = self.main_widget_mut()?;
+            main_widget.content.tree_view = true;
+            main_widget.content.expand_dir(files)?;
+            main_widget.refresh()?;
+            main_widget.select_file(&selected);
+        }
+
+        Ok(())
+    }
+
+    pub fn grep(&mut self) -> HResult<()> {
+        let pattern = self.minibuffer("grep")?;
+        let sender = self.core.get_sender();
+
+        let main_widget = self.main_widget_mut()?;
+        main_widget.content.start_grep(pattern.clone(), sender);
+        main_widget.set_selection(0);
+        main_widget.refresh()?;
+
+        self.show_status(&format!("Searching for: \"{}\"", pattern))
+    }
+
+    pub fn find_recursive(&mut self) -> HResult<()> {
+        let content = &self.main_widget()?.content;
+        let mut finder = Finder::new(&self.cwd.path,
+                                     content.show_hidden,
+                                     content.match_mode,
+                                     content.smart_case);
+        let lines = std::cmp::min(10, term::ysize() as usize / 2);
+        let mut results = vec![];
+        let mut last_input = String::new();
+        let mut selected = 0;
+
+        finder.refresh_while_walking(self.core.get_sender());
+
+        loop {
+            match self.minibuffer_selection("find") {
+                Err(HError::MiniBufferInputUpdated(input)) => {
+                    results = finder.find(&input, lines);
+                    // Only new input starts at the top again
+                    if input != last_input { selected = 0; }
+                    selected = selected.min(results.len().saturating_sub(1));
+                    last_input = input;
+                }
+                Err(HError::MiniBufferSelectionMoved(amount)) => {
+                    let pos = selected as isize + amount;
+                    let last = results.len().saturating_sub(1) as isize;
+                    selected = pos.max(0).min(last) as usize;
+                }
+                Ok(input) => {
+                    // Input left over from last time
+                    if results.is_empty() {
+                        results = finder.find(&input, lines);
+                    }
+                    break;
+                }
+                Err(HError::MiniBufferEmptyInput) |
+                Err(HError::MiniBufferCancelledInput) => {
+                    results.clear();
+                    break;
+                }
+                err @ Err(_) => { err?; }
+            }
+            self.write_to_screen(&finder.render(&results, selected, lines)).log();
+        }
+
+        // Stops the traversal and the refreshing
+        drop(finder);
+
+        // Get rid of the result list
+        self.clear().log();
+
+        let path = match results.get(selected) {
+            Some(path) => path,
+            None => return Ok(())
+        };
+
+        let file = File::new_from_path(path, None)?;
+        let dir = file.parent_as_file()?;
+
+        self.main_widget_goto_wait(&dir)?;
+        self.main_widget_mut()?.select_file(&file);
+        Ok(())
+    }
+
     pub fn quit_with_dir(&self) -> HResult<()> {
         let cwd = self.cwd()?.clone().path;
-        let selected_file = self.selected_file()?;
-        let selected_file = selected_file.path.to_string_lossy();
+        let selected_file = self.selected_file()?.path;
         let selected_files = self.selected_files()?;
+        let selected_files = selected_files
+            .iter()
+            .map(|file| file.path.as_path())
+            .collect::<Vec<_>>();
+
+        let config = self.config();
+        let args = &crate::args::ARGS;
+        let format = args.exit_format.unwrap_or(config.exit_format);
+        let filepath = match args.exit_file.clone().or(config.exit_file.clone()) {
+            Some(path) => path,
+            None => {
+                let mut filepath = dirs_2::home_dir()?;
+                filepath.push(".hunter_cwd");
+                filepath
+            }
+        };
 
-        let selected_files = selected_files.iter().map(|f| {
-            format!("\"{}\" ", &f.path.to_string_lossy())
-        }).collect::<String>();
+        let output = format.format(&cwd, &selected_file, &selected_files);
+
+        crate::shell::write_exit_file(&filepath, &output)?;
+        HError::quit()
+    }
 
-        let mut filepath = dirs_2::home_dir()?;
-        filepath.push(".hunter_cwd");
+    // Chooser mode: hand the opened file, or all selected files, to
+    // whoever started hunter and quit
+    fn choose_files(&self) -> HResult<()> {
+        let args = &crate::args::ARGS;
 
-        let output = format!("HUNTER_CWD=\"{}\"\nF=\"{}\"\nMF=({})\n",
-                             cwd.to_str()?,
-                             selected_file,
-                             selected_files);
+        if let Some(target) = &args.choosefiles {
+            let mut files = self.selected_files()?;
+            if files.is_empty() {
+                files.push(self.selected_file()?);
+            }
+            let paths = files.into_iter().map(|file| file.path).collect::<Vec<_>>();
+            write_chosen_paths(target, &paths, args.null)?;
+        } else if let Some(target) = &args.choosefile {
+            write_chosen_paths(target, &[self.selected_file()?.path], args.null)?;
+        }
 
-        let mut file = std::fs::File::create(filepath)?;
-        file.write(output.as_bytes())?;
         HError::quit()
     }
 
+    // Goes to path if it's a directory, or selects it in its parent
+    pub fn goto_path(&mut self, path: &std::path::Path) -> HResult<()> {
+        let path = path.canonicalize()?;
+        let file = File::new_from_path(&path, None)?;
+
+        if path.is_dir() {
+            return self.main_widget_goto(&file);
+        }
+
+        let dir = file.parent_as_file()?;
+        if dir != self.cwd {
+            self.main_widget_goto(&dir)?;
+        }
+
+        self.main_async_widget_mut()?
+            .widget
+            .on_ready(move |w, _| {
+                w?.select_file(&file);
+                Ok(())
+            })?;
+        Ok(())
+    }
+
     pub fn turbo_cd(&mut self) -> HResult<()> {
         let dir = self.minibuffer("cd")?;
 
@@ -1095,6 +1609,67 @@ impl FileBrowser {
         Ok(())
     }
 
+    // Runs cmd in the background like !, with $s replaced by the
+    // selected files
+    pub fn run_cmd(&mut self, cmd: &str) -> HResult<()> {
+        let cwd = self.cwd()?.clone();
+        let selected_files = match self.selected_files()? {
+            ref files if files.is_empty() => vec![self.selected_file()?],
+            files => files
+        };
+
+        let cmd = crate::proclist::Cmd {
+            cmd: OsString::from(cmd.to_string() + " "),
+            short_cmd: None,
+            args: None,
+            cwd: cwd,
+            cwd_files: Some(selected_files),
+            tab_files: None,
+            tab_paths: None
+        };
+
+        self.proc_view.lock()?.run_proc_subshell(cmd)
+    }
+
+    pub fn plugin_context(&self) -> serde_json::Value {
+        let path = |file: &File| file.path.to_string_lossy().to_string();
+
+        json!({
+            "cwd": path(&self.cwd),
+            "selection": self.selected_file().ok().as_ref().map(path),
+            "selected": self.selected_files()
+                .unwrap_or(vec![])
+                .iter()
+                .map(path)
+                .collect::<Vec<String>>()
+        })
+    }
+
+    // Plugins can take their time, the reply comes back as an event
+    fn run_plugin_action(&self, plugin: Arc<Plugin>, action: Action) {
+        let context = self.plugin_context();
+        let sender = self.core.get_sender();
+
+        std::thread::spawn(move || {
+            let reply = plugin.run_action(&action, context);
+            sender.send(Events::PluginReply(reply)).ok();
+        });
+    }
+
+    // Plugins answer with what hunter should do, all fields are optional
+    fn apply_plugin_reply(&mut self, reply: Value) -> HResult<()> {
+        if let Some(dir) = reply["cd"].as_str() {
+            self.goto_path(std::path::Path::new(dir))?;
+        }
+        if let Some(path) = reply["select"].as_str() {
+            self.goto_path(std::path::Path::new(path))?;
+        }
+        if let Some(status) = reply["status"].as_str() {
+            self.show_status(status).log();
+        }
+        Ok(())
+    }
+
     pub fn run_subshell(&mut self) -> HResult<()> {
         self.core.get_sender().send(Events::InputEnabled(false))?;
 
@@ -1125,10 +1700,27 @@ impl FileBrowser {
 
     pub fn show_procview(&mut self) -> HResult<()> {
         self.preview_widget().map(|preview| preview.cancel_animation()).log();
+        let coords = self.get_coordinates()?.clone();
+        self.proc_view.lock()?.set_coordinates(&coords).log();
         self.proc_view.lock()?.popup()?;
         Ok(())
     }
 
+    pub fn show_hexview(&mut self) -> HResult<()> {
+        let file = self.selected_file()?;
+        if file.is_dir() { return Ok(()) }
+
+        self.preview_widget().map(|preview| preview.cancel_animation()).log();
+        let coords = self.get_coordinates()?.clone();
+        let mut hexview = HexView::new(&self.core, &file)?;
+        hexview.set_coordinates(&coords).log();
+
+        match hexview.popup() {
+            Ok(_) | Err(HError::PopupFinnished) => Ok(()),
+            err @ Err(_) => err
+        }
+    }
+
     pub fn show_log(&mut self) -> HResult<()> {
         self.preview_widget().map(|preview| preview.cancel_animation()).log();
         self.log_view.lock()?.popup()?;
@@ -1136,8 +1728,9 @@ impl FileBrowser {
     }
 
     pub fn get_footer(&self) -> HResult<String> {
+        // Keep to the pane's columns, the bottom row is shared by all panes
         let xsize = self.get_coordinates()?.xsize();
-        let ypos = self.get_coordinates()?.position().y();
+        let xpos = self.get_coordinates()?.xpos();
         let pos = self.main_widget()?.get_selection();
         let file = self.main_widget()?.content.get_files().get(pos).cloned()?;
 
@@ -1158,8 +1751,8 @@ impl FileBrowser {
                                  selection,
                                  file_count,
                                  digits = digits);
-        let count_xpos = xsize - file_count.len() as u16;
-        let count_ypos = ypos + self.get_coordinates()?.ysize();
+        let count_xpos = (xpos - 1 + xsize).saturating_sub(file_count.len() as u16);
+        let count_ypos = term::ysize();
 
         let fs = self.fs_stat.read()?.find_fs(&file.path)?.clone();
 
@@ -1171,7 +1764,7 @@ impl FileBrowser {
                             free_space,
                             total_space);
 
-        let space_xpos = count_xpos - space.len() as u16 - 5; // - 3;
+        let space_xpos = count_xpos.saturating_sub(space.len() as u16 + 5).max(xpos);
 
         let status = format!("{} {}:{} {}{} {}{}",
                              permissions,
@@ -1184,7 +1777,8 @@ impl FileBrowser {
         );
         let status = crate::term::sized_string_u(&status, (xsize-1) as usize);
 
-        let status = format!("{}{}{}{}{}{} | {}",
+        let status = format!("{}{}{}{}{}{}{} | {}",
+                             crate::term::goto_xy(xpos, count_ypos),
                              status,
                              crate::term::header_color(),
                              crate::term::goto_xy(space_xpos, count_ypos),
@@ -1235,7 +1829,23 @@ impl Widget for FileBrowser {
         if &path == "/" { path.pop(); }
 
 
-        let pretty_path = format!("{}/{}{}", path, &color, name );
+        let filters = self.main_widget()
+            .map(|widget| widget.content.get_filters())
+            .unwrap_or_default()
+            .iter()
+            .map(|filter| filter.to_string())
+            .collect::<Vec<String>>();
+
+        let filters = if filters.is_empty() {
+            String::new()
+        } else {
+            format!("  {}[{}]{}",
+                    term::color_yellow(),
+                    filters.join(" "),
+                    term::header_color())
+        };
+
+        let pretty_path = format!("{}/{}{}{}", path, &color, name, filters);
         let sized_path = crate::term::sized_string(&pretty_path, xsize);
         Ok(sized_path)
     }
@@ -1249,6 +1859,7 @@ impl Widget for FileBrowser {
     fn refresh(&mut self) -> HResult<()> {
         self.set_title().log();
         self.columns.refresh().log();
+        self.expand_pending_folds().log();
         self.set_left_selection().log();
         self.set_cwd().log();
         if !self.columns.zoom_active { self.update_preview().log(); }
@@ -1261,7 +1872,54 @@ impl Widget for FileBrowser {
         self.columns.get_drawlist()
     }
 
+    fn on_remote(&mut self, command: RemoteCommand) -> HResult<String> {
+        match command {
+            RemoteCommand::Cd(path) => {
+                if !path.is_dir() {
+                    return HError::remote(&format!("not a directory: {}",
+                                                   path.to_string_lossy()));
+                }
+                self.goto_path(&path)?;
+            }
+            RemoteCommand::Select(path) => self.goto_path(&path)?,
+            RemoteCommand::Cwd => return Ok(remote::cwd_reply(&self.cwd.path)),
+            RemoteCommand::Selection => {
+                let selected = self.selected_file().ok();
+                let selections = self.selected_files()?;
+                let selections = selections
+                    .iter()
+                    .map(|file| file.path.as_path())
+                    .collect::<Vec<_>>();
+                return Ok(remote::selection_reply(selected.as_ref().map(|f| f.path.as_path()),
+                                                  &selections));
+            }
+            _ => HError::remote("not supported here")?
+        }
+        self.update_preview().log();
+        Ok(remote::ok_reply())
+    }
+
+    fn on_plugin_reply(&mut self, reply: HResult<Value>) -> HResult<()> {
+        self.apply_plugin_reply(reply?)?;
+        self.update_preview()
+    }
+
     fn on_key(&mut self, key: Key) -> HResult<()> {
+        // Keys bound by scripts take precedence
+        match self.run_script_binding(key) {
+            Ok(true) => {
+                self.update_preview().log();
+                return Ok(());
+            }
+            Ok(false) => {}
+            err @ Err(_) => { err.log(); return Ok(()); }
+        }
+
+        if let Some((plugin, action)) = PLUGINS.action_for(key) {
+            self.run_plugin_action(plugin, action);
+            return Ok(());
+        }
+
         match key {
             Key::Char(']') => self.move_down_left_widget()?,
             Key::Char('[') => self.move_up_left_widget()?,
@@ -1272,6 +1930,8 @@ impl Widget for FileBrowser {
             Key::Char('Q') => { self.quit_with_dir()?; },
             Key::Right | Key::Char('l') => { self.enter_dir()?; },
             Key::Char('L') => { self.open_bg()?; },
+            Key::Char('O') => { self.open_with_menu()?; },
+            Key::Char('X') => { self.show_hexview()?; },
             Key::Left | Key::Char('h') => { self.go_back()?; },
             Key::Char('-') => { self.goto_prev_cwd()?; },
             Key::Char('`') => { self.goto_bookmark()?; },
@@ -1280,6 +1940,12 @@ impl Widget for FileBrowser {
             Key::Char('g') => self.show_log()?,
             Key::Char('z') => self.run_subshell()?,
             Key::Char('c') => self.toggle_colums(),
+            Key::Char('T') => self.toggle_tree_view()?,
+            Key::Char('e') => self.toggle_fold()?,
+            Key::Char('F') => self.toggle_flat_view()?,
+            Key::Alt('f') => self.find_recursive()?,
+            Key::Alt('g') => self.grep()?,
+            Key::Char('V') => self.select_grep_hits()?,
             _ => { self.main_widget_mut()?.on_key(key)?; },
         }
         if !self.columns.zoom_active { self.update_preview().log(); }
@@ -4,8 +4,11 @@ use std::fs::Metadata;
 use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
 use std::sync::mpsc::Sender;
-use std::hash::{Hash, Hasher};
+use std::hash::{Hash, Hasher, BuildHasher};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::hash_map::RandomState;
 
 use lscolors::LsColors;
 use tree_magic;
@@ -25,6 +28,8 @@ use crate::fail::{HResult, HError, ErrorLog};
 use crate::dirty::{AsyncDirtyBit, DirtyBit, Dirtyable};
 use crate::widget::Events;
 use crate::icon::Icons;
+use crate::fuzzy::MatchMode;
+use crate::filter::Filter;
 
 
 lazy_static! {
@@ -102,14 +107,165 @@ pub struct Files {
     pub meta_upto: Option<usize>,
     pub meta_updated: bool,
     pub sort: SortBy,
+    pub case_insensitive: bool,
+    pub random_seed: u64,
+    // Direction of the main sort mode, like the - in a sort chain
+    pub sort_reverse: bool,
+    // None until a chain is picked for this directory or taken from
+    // the config, so an empty chain can be chosen, too
+    pub sort_chain: Option<Vec<SortKey>>,
     pub dirs_first: bool,
     pub reverse: bool,
     pub show_hidden: bool,
-    pub filter: Option<String>,
+    pub filters: Vec<Filter>,
+    pub match_mode: MatchMode,
+    pub smart_case: bool,
+    pub tree_view: bool,
+    pub subtrees: Vec<Files>,
+    pub flat_view: Option<FlatListing>,
     pub dirty: DirtyBit,
     pub dirty_meta: AsyncDirtyBit,
+    mime_pool: Option<MimePool>,
 }
 
+// Detects the mime types of a directory and its subtrees, kept until
+// all of them are known. Dropping it lets the threads exit, which
+// sends WidgetReady once the last one is done
+#[derive(Clone)]
+struct MimePool(Arc<ThreadPool>);
+
+impl PartialEq for MimePool {
+    fn eq(&self, other: &MimePool) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MimePool {}
+
+impl Hash for MimePool {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+impl std::fmt::Debug for MimePool {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter, "MimePool")
+    }
+}
+
+// Recursive listing running in the background. Found files are
+// picked up in batches by take_flat_pending() and shown in the order
+// they were found, until everything is sorted once the listing is done
+pub struct FlatListing {
+    pub depth: usize,
+    pub pattern: Option<String>,
+    pending: Arc<Mutex<Vec<File>>>,
+    finished: Arc<AtomicBool>,
+    // Set once everything is taken, nothing is picked up afterwards
+    done: bool,
+    stale: Stale,
+}
+
+impl FlatListing {
+    fn new(depth: usize, pattern: Option<String>) -> FlatListing {
+        FlatListing {
+            depth: depth,
+            pattern: pattern,
+            pending: Arc::new(Mutex::new(vec![])),
+            finished: Arc::new(AtomicBool::new(false)),
+            done: false,
+            stale: Stale::new()
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(AtomicOrdering::Relaxed)
+    }
+}
+
+impl PartialEq for FlatListing {
+    fn eq(&self, other: &FlatListing) -> bool {
+        self.depth == other.depth &&
+            self.pattern == other.pattern &&
+            Arc::ptr_eq(&self.pending, &other.pending)
+    }
+}
+
+impl Eq for FlatListing {}
+
+impl Hash for FlatListing {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.depth.hash(state);
+        self.pattern.hash(state);
+    }
+}
+
+impl Clone for FlatListing {
+    fn clone(&self) -> Self {
+        FlatListing {
+            depth: self.depth,
+            pattern: self.pattern.clone(),
+            pending: self.pending.clone(),
+            finished: self.finished.clone(),
+            done: self.done,
+            stale: self.stale.clone()
+        }
+    }
+}
+
+impl std::fmt::Debug for FlatListing {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(formatter,
+               "FlatListing {{ depth: {}, pattern: {:?} }}",
+               self.depth,
+               self.pattern)
+    }
+}
+
+fn list_flat(root: PathBuf,
+             max_depth: usize,
+             pending: Arc<Mutex<Vec<File>>>,
+             dirty_meta: AsyncDirtyBit,
+             stale: Stale,
+             sender: Sender<Events>) -> HResult<()> {
+    let mut dirs = VecDeque::new();
+    dirs.push_back((root.clone(), 1));
+
+    // Breadth first, so the closest files show up first
+    while let Some((dir, depth)) = dirs.pop_front() {
+        let direntries = match std::fs::read_dir(&dir) {
+            Ok(direntries) => direntries,
+            Err(_) => continue
+        };
+
+        let mut files = vec![];
+
+        for direntry in direntries.filter_map(|direntry| direntry.ok()) {
+            if stale.is_stale()? { return Ok(()) }
+
+            let path = direntry.path();
+            let name = path.strip_prefix(&root)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string();
+            let file = File::new_with_stale(&name,
+                                            path.clone(),
+                                            Some(dirty_meta.clone()),
+                                            stale.clone());
+
+            if file.is_dir() && depth < max_depth {
+                dirs.push_back((path, depth + 1));
+            }
+
+            files.push(file);
+        }
+
+        pending.lock()?.append(&mut files);
+        sender.send(Events::WidgetReady).ok();
+    }
+    Ok(())
+}
+
+
 impl Index<usize> for Files {
     type Output = File;
     fn index(&self, pos: usize) -> &File {
@@ -157,12 +313,22 @@ impl Files {
             meta_upto: None,
             meta_updated: false,
             sort: SortBy::Name,
+            case_insensitive: false,
+            random_seed: 0,
+            sort_reverse: false,
+            sort_chain: None,
             dirs_first: true,
             reverse: false,
             show_hidden: true,
-            filter: None,
+            filters: vec![],
+            match_mode: MatchMode::Substring,
+            smart_case: false,
+            tree_view: false,
+            subtrees: vec![],
+            flat_view: None,
             dirty: dirty,
             dirty_meta: dirty_meta,
+            mime_pool: None,
         };
 
         files.sort();
@@ -214,12 +380,22 @@ impl Files {
             meta_upto: None,
             meta_updated: false,
             sort: SortBy::Name,
+            case_insensitive: false,
+            random_seed: 0,
+            sort_reverse: false,
+            sort_chain: None,
             dirs_first: true,
             reverse: false,
             show_hidden: true,
-            filter: None,
+            filters: vec![],
+            match_mode: MatchMode::Substring,
+            smart_case: false,
+            tree_view: false,
+            subtrees: vec![],
+            flat_view: None,
             dirty: dirty,
             dirty_meta: dirty_meta,
+            mime_pool: None,
         };
 
         files.sort();
@@ -232,116 +408,398 @@ impl Files {
     }
 
     pub fn get_file_mut(&mut self, index: usize) -> Option<&mut File> {
-        let filter = self.filter.clone();
-        let show_hidden = self.show_hidden;
-
-        let file = self.files
-            .iter_mut()
-            .filter(|f|
-                    f.kind == Kind::Placeholder ||
-                    !(filter.is_some() &&
-                      !f.name.contains(filter.as_ref().unwrap())))
-            .filter(|f| !(!show_hidden && f.name.starts_with(".")))
-            .nth(index);
-        file
+        self.get_files_mut().into_iter().nth(index)
     }
 
-    pub fn get_files(&self) -> Vec<&File> {
+    fn get_visible_files(&self) -> Vec<&File> {
         self.files
             .iter()
             .filter(|f|
                     f.kind == Kind::Placeholder ||
-                    (!(self.filter.is_some() &&
-                      !f.name.contains(self.filter.as_ref().unwrap()))))
+                    self.filters
+                        .iter()
+                        .all(|filter| filter.matches(f,
+                                                     self.match_mode,
+                                                     self.smart_case)))
             .filter(|f| !(!self.show_hidden && f.name.starts_with(".")))
             .collect()
     }
 
+    pub fn get_files(&self) -> Vec<&File> {
+        if self.subtrees.is_empty() {
+            return self.get_visible_files();
+        }
+
+        // Contents of expanded directories go right below them
+        self.get_visible_files()
+            .into_iter()
+            .flat_map(|file| {
+                let mut files = vec![file];
+                if let Some(subtree) = self.get_subtree(&file.path) {
+                    files.extend(subtree.get_files());
+                }
+                files
+            })
+            .collect()
+    }
+
     pub fn get_files_mut(&mut self) -> Vec<&mut File> {
-        let filter = self.filter.clone();
+        let filters = self.filters.clone();
+        let match_mode = self.match_mode;
+        let smart_case = self.smart_case;
         let show_hidden = self.show_hidden;
+        let mut subtrees = self.subtrees
+            .iter_mut()
+            .map(|subtree| (subtree.directory.path.clone(), subtree))
+            .collect::<HashMap<PathBuf, &mut Files>>();
+
         self.files
             .iter_mut()
             .filter(|f|
                     f.kind == Kind::Placeholder ||
-                    !(filter.is_some() &&
-                      !f.name.contains(filter.as_ref().unwrap())))
+                    filters
+                        .iter()
+                        .all(|filter| filter.matches(f, match_mode, smart_case)))
             .filter(|f| !(!show_hidden && f.name.starts_with(".")))
+            .flat_map(|file| {
+                let subtree_files = subtrees
+                    .remove(&file.path)
+                    .map(|subtree| subtree.get_files_mut())
+                    .unwrap_or(vec![]);
+                std::iter::once(file).chain(subtree_files)
+            })
             .collect()
     }
 
-    pub fn sort(&mut self) {
-        match self.sort {
-            SortBy::Name => self
-                .files
-                .sort_by(|a, b| {
-                    compare_str(&a.name, &b.name)
-                }),
-            SortBy::Size => {
-                self.meta_all_sync().log();
-                self.files.sort_by(|a, b| {
-                    match (a.meta(), b.meta()) {
-                        (Ok(a_meta), Ok(b_meta)) => {
-                            if a_meta.size() == b_meta.size() {
-                                compare_str(&b.name, &a.name)
-                            } else {
-                                a_meta.size().cmp(&b_meta.size()).reverse()
-                            }
-
-                        }
-                        _ => return std::cmp::Ordering::Equal
-                    }
+    pub fn start_flat_view(&mut self, depth: usize, sender: Sender<Events>) {
+        let root = self.directory.path.clone();
+
+        self.start_listing(FlatListing::new(depth, None),
+                           sender.clone(),
+                           move |pending, dirty_meta, stale| {
+            list_flat(root, depth, pending, dirty_meta, stale, sender)
+        });
+    }
+
+    pub fn start_grep(&mut self,
+                      pattern: String,
+                      sender: Sender<Events>) {
+        let root = self.directory.path.clone();
+        let show_hidden = self.show_hidden;
+        let grep_pattern = pattern.clone();
+
+        self.start_listing(FlatListing::new(0, Some(pattern)),
+                           sender.clone(),
+                           move |pending, dirty_meta, stale| {
+            crate::grep::grep(root,
+                              grep_pattern,
+                              show_hidden,
+                              pending,
+                              dirty_meta,
+                              stale,
+                              sender)
+        });
+    }
 
+    fn start_listing<F>(&mut self,
+                        listing: FlatListing,
+                        sender: Sender<Events>,
+                        lister: F)
+    where
+        F: FnOnce(Arc<Mutex<Vec<File>>>, AsyncDirtyBit, Stale) -> HResult<()>
+        + Send + 'static {
+        self.stop_flat_view();
 
-                });
+        self.tree_view = false;
+        self.subtrees.clear();
+        self.files.clear();
+        self.meta_upto = None;
+
+        let pending = listing.pending.clone();
+        let dirty_meta = self.dirty_meta.clone();
+        let stale = listing.stale.clone();
+        let finished = listing.finished.clone();
+
+        rayon::spawn(move || {
+            lister(pending, dirty_meta, stale.clone()).log();
+
+            if !stale.is_stale().unwrap_or(true) {
+                finished.store(true, AtomicOrdering::Relaxed);
+                sender.send(Events::WidgetReady).ok();
             }
-            SortBy::MTime => {
-                self.meta_all_sync().log();
-                self.files.sort_by(|a, b| {
-                    match (a.meta(), b.meta()) {
-                        (Ok(a_meta), Ok(b_meta)) => {
-                            if a_meta.mtime() == b_meta.mtime() {
-                                compare_str(&b.name, &a.name)
-                            } else {
-                                a_meta.mtime().cmp(&b_meta.mtime()).reverse()
-                            }
-
-                        }
-                        _ => return std::cmp::Ordering::Equal
-                    }
-                });
+        });
+
+        self.flat_view = Some(listing);
+        self.set_dirty();
+    }
+
+    pub fn stop_flat_view(&mut self) {
+        if let Some(flat_view) = self.flat_view.take() {
+            flat_view.stale.set_stale().log();
+        }
+    }
+
+    pub fn take_flat_pending(&mut self) -> HResult<usize> {
+        let (mut files, finished) = match &self.flat_view {
+            Some(flat_view) if !flat_view.done => {
+                // Check before taking the files, so none are missed
+                let finished = flat_view.is_finished();
+                let files = std::mem::replace(&mut *flat_view.pending.lock()?,
+                                              vec![]);
+                (files, finished)
             }
+            _ => return Ok(0)
         };
 
-        if self.dirs_first {
-            self.files.sort_by(|a, b| {
-                if a.is_dir() && !b.is_dir() {
-                    Ordering::Less
-                } else {
-                    Ordering::Equal
-                }
-            });
-            self.files.sort_by(|a, b| {
-                if a.name.starts_with(".") && !b.name.starts_with(".") {
-                    Ordering::Less
+        let num = files.len();
+
+        if num > 0 {
+            self.remove_placeholder();
+            self.files.append(&mut files);
+            self.meta_upto = None;
+            self.set_dirty();
+        }
+
+        // Sorting after every batch would get slow with lots of files
+        if finished {
+            self.flat_view.as_mut()?.done = true;
+            self.sort();
+            return Ok(num.max(1));
+        }
+
+        Ok(num)
+    }
+
+    // Stops the search and replaces the grep hits with one selected
+    // entry per matching file, to use them like any other selection
+    pub fn select_grep_hits(&mut self) -> HResult<usize> {
+        let flat_view = self.flat_view.as_mut()?;
+        if flat_view.pattern.is_none() {
+            return HError::log(String::from("Not showing grep results"));
+        }
+
+        flat_view.stale.set_stale()?;
+        flat_view.done = true;
+        let pending = std::mem::replace(&mut *flat_view.pending.lock()?, vec![]);
+
+        let root = self.directory.path.clone();
+        let mut seen = HashSet::new();
+        self.files = self.files
+            .drain(..)
+            .chain(pending)
+            .filter(|file| file.line.is_some() && seen.insert(file.path.clone()))
+            .map(|mut file| {
+                file.name = file.path
+                    .strip_prefix(&root)
+                    .unwrap_or(&file.path)
+                    .to_string_lossy()
+                    .to_string();
+                file.line = None;
+                file.selected = true;
+                file
+            })
+            .collect();
+
+        self.meta_upto = None;
+        self.sort();
+        Ok(self.files.len())
+    }
+
+    pub fn toggle_tree_view(&mut self) {
+        self.tree_view = !self.tree_view;
+
+        if !self.tree_view {
+            self.subtrees.clear();
+        }
+
+        self.set_dirty();
+    }
+
+    pub fn get_subtree(&self, dir: &Path) -> Option<&Files> {
+        self.subtrees
+            .iter()
+            .find(|subtree| subtree.directory.path == dir)
+    }
+
+    fn find_subtree_mut(&mut self, dir: &Path) -> Option<&mut Files> {
+        if self.directory.path == dir {
+            return Some(self);
+        }
+
+        self.subtrees
+            .iter_mut()
+            .find_map(|subtree| subtree.find_subtree_mut(dir))
+    }
+
+    pub fn is_expanded(&self, dir: &Path) -> bool {
+        self.subtrees
+            .iter()
+            .any(|subtree| subtree.directory.path == dir ||
+                 subtree.is_expanded(dir))
+    }
+
+    pub fn expand_dir(&mut self, mut subtree: Files) -> HResult<()> {
+        let parent = subtree.directory.parent()?;
+        subtree.meta_all_sync().log();
+
+        let tree = self.find_subtree_mut(&parent)?;
+        tree.subtrees.push(subtree);
+        tree.update_subtrees();
+
+        self.set_dirty();
+        Ok(())
+    }
+
+    pub fn collapse_dir(&mut self, dir: &Path) {
+        self.subtrees.retain(|subtree| subtree.directory.path != dir);
+
+        for subtree in self.subtrees.iter_mut() {
+            subtree.collapse_dir(dir);
+        }
+
+        self.set_dirty();
+    }
+
+    fn update_subtrees(&mut self) {
+        for subtree in self.subtrees.iter_mut() {
+            subtree.sort = self.sort;
+            subtree.case_insensitive = self.case_insensitive;
+            subtree.random_seed = self.random_seed;
+            subtree.sort_reverse = self.sort_reverse;
+            subtree.sort_chain = self.sort_chain.clone();
+            subtree.dirs_first = self.dirs_first;
+            subtree.reverse = self.reverse;
+            subtree.show_hidden = self.show_hidden;
+            subtree.filters = self.filters.clone();
+            subtree.match_mode = self.match_mode;
+            subtree.smart_case = self.smart_case;
+            subtree.sort();
+        }
+    }
+
+    // Indentation guides for each line returned by get_files()
+    pub fn get_tree_guides(&self) -> Vec<String> {
+        if !self.tree_view { return vec![] }
+        self.tree_guides("")
+    }
+
+    fn tree_guides(&self, indent: &str) -> Vec<String> {
+        let files = self.get_visible_files();
+        let last = files.len().saturating_sub(1);
+
+        files.iter()
+            .enumerate()
+            .flat_map(|(i, file)| {
+                let (guide, child_indent) = if i == last {
+                    ("`-- ", "    ")
                 } else {
-                    Ordering::Equal
+                    ("|-- ", "|   ")
+                };
+
+                let mut guides = vec![format!("{}{}", indent, guide)];
+
+                if let Some(subtree) = self.get_subtree(&file.path) {
+                    let child_indent = format!("{}{}", indent, child_indent);
+                    guides.extend(subtree.tree_guides(&child_indent));
                 }
-            });
+                guides
+            })
+            .collect()
+    }
+
+    // The chosen sort mode followed by the secondary criteria
+    pub fn full_sort_chain(&self) -> Vec<SortKey> {
+        let mut chain = vec![SortKey { by: self.sort, reverse: self.sort_reverse }];
+        chain.extend(self.sort_chain.iter().flatten().cloned());
+        chain
+    }
+
+    // Sorts with a single comparator made from dirs_first, the sort
+    // chain and the name as final tie-break. Each file's sort values
+    // are computed once per sort instead of once per comparison
+    pub fn sort(&mut self) {
+        let chain = self.full_sort_chain();
+        if chain.iter().any(|key| key.by.needs_meta()) {
+            self.meta_all_sync().log();
         }
 
+        let dirs_first = self.dirs_first;
+        let case_insensitive = self.case_insensitive;
+        let random_seed = self.random_seed;
+        // Names go in the same direction as the main sort mode, so equal
+        // sizes or times are still ordered by name descending
+        let name_descending = chain[0].by.descending() != chain[0].reverse;
+
+        let mut keyed_files = self.files
+            .drain(..)
+            .map(|file| {
+                let mut values = vec![];
+
+                if dirs_first {
+                    values.push((SortValue::Flag(!file.name.starts_with(".")), false));
+                    values.push((SortValue::Flag(!file.is_dir()), false));
+                }
+
+                for key in &chain {
+                    let value = SortValue::new(key.by, &file, case_insensitive, random_seed);
+                    values.push((value, key.by.descending() != key.reverse));
+                }
+
+                let name = SortValue::new(SortBy::Name, &file, case_insensitive, random_seed);
+                values.push((name, name_descending));
+
+                (values, file)
+            })
+            .collect::<Vec<_>>();
+
+        keyed_files.sort_by(|(a_values, _), (b_values, _)| {
+            a_values.iter()
+                .zip(b_values.iter())
+                .map(|((a, descending), (b, _))| {
+                    let ordering = a.compare(b);
+                    if *descending { ordering.reverse() } else { ordering }
+                })
+                .find(|ordering| *ordering != Ordering::Equal)
+                .unwrap_or(Ordering::Equal)
+        });
+
+        self.files = keyed_files.into_iter().map(|(_, file)| file).collect();
+
         if self.reverse {
             self.files.reverse();
         }
+
+        self.update_subtrees();
         self.set_dirty();
     }
 
     pub fn cycle_sort(&mut self) {
-        self.sort = match self.sort {
+        let sort = match self.sort {
             SortBy::Name => SortBy::Size,
             SortBy::Size => SortBy::MTime,
-            SortBy::MTime => SortBy::Name,
+            SortBy::MTime => SortBy::CTime,
+            SortBy::CTime => SortBy::ATime,
+            SortBy::ATime => SortBy::Extension,
+            SortBy::Extension => SortBy::Version,
+            SortBy::Version => SortBy::Mime,
+            SortBy::Mime => SortBy::Owner,
+            SortBy::Owner => SortBy::Random,
+            SortBy::Random => SortBy::Name,
         };
+        self.set_sort(sort);
+    }
+
+    pub fn set_sort(&mut self, sort: SortBy) {
+        // Shuffle again every time random sorting is picked
+        if sort == SortBy::Random {
+            self.random_seed = RandomState::new().build_hasher().finish();
+        }
+        self.sort = sort;
+        self.sort_reverse = false;
+    }
+
+    pub fn toggle_case_sensitive(&mut self) {
+        self.case_insensitive = !self.case_insensitive;
     }
 
     pub fn reverse_sort(&mut self) {
@@ -350,6 +808,7 @@ impl Files {
 
     pub fn toggle_hidden(&mut self) {
         self.show_hidden = !self.show_hidden;
+        self.update_subtrees();
         self.set_dirty();
 
         if self.show_hidden == true && self.len() > 1 {
@@ -442,10 +901,19 @@ impl Files {
     }
 
     pub fn find_file_with_name(&self, name: &str) -> Option<&File> {
+        let match_mode = self.match_mode;
+
+        // Take the best match, the first one wins on equal scores
         self.get_files()
-            .iter()
-            .find(|f| f.name.to_lowercase().contains(name))
-            .cloned()
+            .into_iter()
+            .filter_map(|f| Some((match_mode.score(name, &f.name)?, f)))
+            .fold(None, |best: Option<(i64, &File)>, (score, f)| {
+                match best {
+                    Some(best) if best.0 >= score => Some(best),
+                    _ => Some((score, f))
+                }
+            })
+            .map(|(_, f)| f)
     }
 
     pub fn find_file_with_path(&mut self, path: &Path) -> Option<&mut File> {
@@ -469,6 +937,10 @@ impl Files {
     }
 
     pub fn meta_upto(&mut self, to: usize, sender: Option<Sender<Events>>) {
+        if self.needs_mime() {
+            self.take_mimes(sender.clone());
+        }
+
         let meta_files = if self.meta_upto > Some(to) {
             self.meta_upto.unwrap()
         } else {
@@ -502,14 +974,51 @@ impl Files {
         self.meta_upto = Some(meta_files);
     }
 
+    fn needs_mime(&self) -> bool {
+        self.full_sort_chain().iter().any(|key| key.by == SortBy::Mime)
+    }
+
+    // Sorting by mime waits for all of them, the files are sorted
+    // again once the last one is known
+    fn take_mimes(&mut self, sender: Option<Sender<Events>>) {
+        let pool = self.mime_pool
+            .get_or_insert_with(|| MimePool(Arc::new(make_pool(sender))))
+            .0
+            .clone();
+
+        if self.take_mimes_pooled(&pool) {
+            self.mime_pool = None;
+        }
+    }
+
+    fn take_mimes_pooled(&mut self, pool: &ThreadPool) -> bool {
+        let all_known = |files: &[File]| files.iter().all(|file| file.mime().is_some());
+        if all_known(&self.files) { return true }
+
+        for file in self.files.iter_mut() {
+            file.take_mime(pool, &mut self.meta_updated).ok();
+        }
+
+        for subtree in self.subtrees.iter_mut() {
+            subtree.take_mimes_pooled(pool);
+        }
+
+        if all_known(&self.files) {
+            self.sort();
+            return true;
+        }
+        false
+    }
+
     pub fn meta_set_fresh(&self) -> HResult<()> {
         self.files.get(0)?.meta.set_fresh()?;
         Ok(())
     }
 
 
-    pub fn set_filter(&mut self, filter: Option<String>) {
-        self.filter = filter;
+    pub fn set_filters(&mut self, filters: Vec<Filter>) {
+        self.filters = filters;
+        self.update_subtrees();
 
         // Do this first, so we know len() == 0 needs a placeholder
         self.remove_placeholder();
@@ -522,8 +1031,21 @@ impl Files {
         self.set_dirty();
     }
 
-    pub fn get_filter(&self) -> Option<String> {
-        self.filter.clone()
+    pub fn push_filter(&mut self, filter: Filter) {
+        let mut filters = self.filters.clone();
+        filters.push(filter);
+        self.set_filters(filters);
+    }
+
+    pub fn pop_filter(&mut self) -> Option<Filter> {
+        let mut filters = self.filters.clone();
+        let filter = filters.pop();
+        self.set_filters(filters);
+        filter
+    }
+
+    pub fn get_filters(&self) -> Vec<Filter> {
+        self.filters.clone()
     }
 
     pub fn len(&self) -> usize {
@@ -531,7 +1053,13 @@ impl Files {
     }
 
     pub fn get_selected(&self) -> Vec<&File> {
-        self.files.iter().filter(|f| f.is_selected()).collect()
+        self.files
+            .iter()
+            .filter(|f| f.is_selected())
+            .chain(self.subtrees
+                   .iter()
+                   .flat_map(|subtree| subtree.get_selected()))
+            .collect()
     }
 }
 
@@ -548,33 +1076,249 @@ impl std::fmt::Display for SortBy {
             SortBy::Name => "name",
             SortBy::Size => "size",
             SortBy::MTime => "mtime",
+            SortBy::CTime => "ctime",
+            SortBy::ATime => "atime",
+            SortBy::Extension => "extension",
+            SortBy::Version => "version",
+            SortBy::Mime => "mime",
+            SortBy::Owner => "owner",
+            SortBy::Random => "random",
         };
         write!(formatter, "{}", text)
     }
 }
 
+impl std::str::FromStr for SortBy {
+    type Err = HError;
+
+    fn from_str(text: &str) -> HResult<SortBy> {
+        let sort = match text {
+            "name" => SortBy::Name,
+            "size" => SortBy::Size,
+            "mtime" => SortBy::MTime,
+            "ctime" => SortBy::CTime,
+            "atime" => SortBy::ATime,
+            "extension" | "ext" => SortBy::Extension,
+            "version" => SortBy::Version,
+            "mime" => SortBy::Mime,
+            "owner" => SortBy::Owner,
+            "random" => SortBy::Random,
+            _ => HError::log(format!("Unknown sort mode: {}", text))?
+        };
+        Ok(sort)
+    }
+}
+
+impl SortBy {
+    // Biggest/newest first feels more natural for these
+    fn descending(&self) -> bool {
+        match self {
+            SortBy::Size |
+            SortBy::MTime |
+            SortBy::CTime |
+            SortBy::ATime => true,
+            _ => false
+        }
+    }
+
+    fn needs_meta(&self) -> bool {
+        match self {
+            SortBy::Size |
+            SortBy::MTime |
+            SortBy::CTime |
+            SortBy::ATime |
+            SortBy::Owner => true,
+            _ => false
+        }
+    }
+}
+
+// One link of a sort chain like "ext,-mtime", where - flips the
+// direction of that key
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SortKey {
+    pub by: SortBy,
+    pub reverse: bool,
+}
+
+impl SortKey {
+    pub fn parse_chain(text: &str) -> HResult<Vec<SortKey>> {
+        text.split(",")
+            .map(|key| key.trim())
+            .filter(|key| !key.is_empty())
+            .map(|key| key.parse())
+            .collect()
+    }
+}
+
+impl std::str::FromStr for SortKey {
+    type Err = HError;
+
+    fn from_str(text: &str) -> HResult<SortKey> {
+        let reverse = text.starts_with("-");
+        let by = text.trim_start_matches("-").parse()?;
+        Ok(SortKey { by: by, reverse: reverse })
+    }
+}
+
+impl std::fmt::Display for SortKey {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let prefix = if self.reverse { "-" } else { "" };
+        write!(formatter, "{}{}", prefix, self.by)
+    }
+}
+
+enum SortValue {
+    Flag(bool),
+    Number(Option<i64>),
+    Text(String),
+    Version(Vec<VersionPart>),
+}
+
+impl SortValue {
+    fn new(by: SortBy,
+           file: &File,
+           case_insensitive: bool,
+           random_seed: u64) -> SortValue {
+        let text = |text: &str| {
+            if case_insensitive {
+                SortValue::Text(text.to_lowercase())
+            } else {
+                SortValue::Text(text.to_string())
+            }
+        };
+        let meta = file.meta().ok();
+
+        match by {
+            SortBy::Name => text(&file.name),
+            SortBy::Size => SortValue::Number(meta.map(|meta| meta.size() as i64)),
+            SortBy::MTime => SortValue::Number(meta.map(|meta| meta.mtime())),
+            SortBy::CTime => SortValue::Number(meta.map(|meta| meta.ctime())),
+            SortBy::ATime => SortValue::Number(meta.map(|meta| meta.atime())),
+            SortBy::Extension => {
+                let ext = file.path.extension().map(|ext| ext.to_string_lossy());
+                text(&ext.unwrap_or_default())
+            }
+            SortBy::Version => SortValue::Version(version_parts(&file.name)),
+            // Detected in the background, see Files::take_mimes()
+            SortBy::Mime => SortValue::Text(file.mime().cloned().unwrap_or_default()),
+            SortBy::Owner => {
+                let owner = meta
+                    .and_then(|meta| get_user_by_uid(meta.uid()))
+                    .map(|user| user.name().to_string_lossy().to_string())
+                    .unwrap_or_default();
+                SortValue::Text(owner)
+            }
+            SortBy::Random => {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                random_seed.hash(&mut hasher);
+                file.name.hash(&mut hasher);
+                SortValue::Number(Some(hasher.finish() as i64))
+            }
+        }
+    }
+
+    fn compare(&self, other: &SortValue) -> Ordering {
+        match (self, other) {
+            (SortValue::Flag(a), SortValue::Flag(b)) => a.cmp(b),
+            (SortValue::Number(a), SortValue::Number(b)) => a.cmp(b),
+            (SortValue::Text(a), SortValue::Text(b)) => compare_str(a, b),
+            (SortValue::Version(a), SortValue::Version(b)) => compare_versions(a, b),
+            _ => Ordering::Equal
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum SortBy {
     Name,
     Size,
     MTime,
+    CTime,
+    ATime,
+    Extension,
+    Version,
+    Mime,
+    Owner,
+    Random,
 }
 
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum VersionPart {
+    PreRelease(String),
+    Number(u64),
+    Text(String),
+}
 
-impl PartialEq for File {
-    fn eq(&self, other: &File) -> bool {
-        if self.path == other.path {
-            true
+// Splits "foo-1.10.0-rc2" into foo, 1, 10, 0, rc, 2. Separators are
+// dropped, so only the numbers and words are compared
+fn version_parts(name: &str) -> Vec<VersionPart> {
+    let mut parts = vec![];
+    let mut chars = name.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                number.push(c);
+                chars.next();
+            }
+            parts.push(VersionPart::Number(number.parse().unwrap_or(std::u64::MAX)));
+        } else if c.is_alphanumeric() {
+            let mut text = String::new();
+            while let Some(&c) = chars.peek()
+                .filter(|c| c.is_alphanumeric() && !c.is_ascii_digit()) {
+                text.push(c);
+                chars.next();
+            }
+            let text = text.to_lowercase();
+            match text.as_str() {
+                "alpha" | "beta" | "pre" | "rc" => parts.push(VersionPart::PreRelease(text)),
+                _ => parts.push(VersionPart::Text(text))
+            }
         } else {
-            false
+            chars.next();
+        }
+    }
+
+    parts
+}
+
+// Like compare_str, but pre-releases come before the actual release,
+// so "1.0-rc1" sorts before "1.0" and "1.0.1"
+fn compare_versions(a_parts: &[VersionPart], b_parts: &[VersionPart]) -> Ordering {
+    for (a_part, b_part) in a_parts.iter().zip(b_parts.iter()) {
+        match a_part.cmp(b_part) {
+            Ordering::Equal => continue,
+            ordering => return ordering
         }
     }
+
+    let is_pre_release = |part: Option<&VersionPart>| match part {
+        Some(VersionPart::PreRelease(_)) => true,
+        _ => false
+    };
+
+    match a_parts.len().cmp(&b_parts.len()) {
+        Ordering::Less if is_pre_release(b_parts.get(a_parts.len())) => Ordering::Greater,
+        Ordering::Greater if is_pre_release(a_parts.get(b_parts.len())) => Ordering::Less,
+        ordering => ordering
+    }
+}
+
+
+impl PartialEq for File {
+    fn eq(&self, other: &File) -> bool {
+        // Grep hits in the same file differ only by line
+        self.path == other.path && self.line == other.line
+    }
 }
 
 impl Hash for File {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.name.hash(state);
         self.path.hash(state);
+        self.line.hash(state);
     }
 }
 
@@ -600,7 +1344,10 @@ pub struct File {
     pub dirty_meta: Option<AsyncDirtyBit>,
     pub meta_processed: bool,
     pub selected: bool,
-    pub tag: Option<bool>
+    pub tag: Option<bool>,
+    pub line: Option<usize>,
+    // Only detected when needed, see take_mime()
+    pub mime: Option<Async<String>>
 }
 
 impl File {
@@ -626,6 +1373,8 @@ impl File {
             color: None,
             selected: false,
             tag: tag,
+            line: None,
+            mime: None
         }
     }
 
@@ -655,6 +1404,8 @@ impl File {
             color: None,
             selected: false,
             tag: tag,
+            line: None,
+            mime: None
         }
     }
 
@@ -732,6 +1483,53 @@ impl File {
         Ok(self.meta.get()?)
     }
 
+    pub fn mime(&self) -> Option<&String> {
+        self.mime.as_ref()?.get().ok()
+    }
+
+    fn make_async_mime(path: &PathBuf,
+                       dirty_meta: Option<AsyncDirtyBit>) -> Async<String> {
+        let path = path.clone();
+
+        let mut mime = Async::new(move |stale: &Stale| {
+            if stale.is_stale()? { HError::stale()? }
+            Ok(tree_magic::from_filepath(&path))
+        });
+
+        dirty_meta.map(|mut d|
+            mime.on_ready(move |_,_| {
+                d.set_dirty();
+
+                Ok(())
+            }).log()
+        );
+        mime
+    }
+
+    // Detecting the mime type reads the file, so it's done only once
+    // per file, in the background like the metadata
+    fn take_mime(&mut self,
+                 pool: &ThreadPool,
+                 meta_updated: &mut bool) -> HResult<()> {
+        if self.mime.is_none() {
+            self.mime = Some(File::make_async_mime(&self.path, self.dirty_meta.clone()));
+        }
+
+        let mime = self.mime.as_mut()?;
+        if let Ok(_) = mime.value { return Ok(()) }
+
+        if !mime.is_running() {
+            mime.run_pooled(Some(&*pool))?;
+        }
+
+        if mime.is_ready() {
+            mime.pull_async()?;
+            *meta_updated = true;
+        }
+
+        Ok(())
+    }
+
     fn take_dirsize(&mut self,
                     pool: &ThreadPool,
                     meta_updated: &mut bool) -> HResult<()> {
@@ -1027,3 +1825,62 @@ impl File {
         self.path.short_string()
     }
 }
+
+
+#[cfg(test)]
+mod test {
+    use super::{compare_versions, version_parts, SortBy, SortKey, VersionPart};
+    use std::cmp::Ordering;
+
+    fn compare(a: &str, b: &str) -> Ordering {
+        compare_versions(&version_parts(a), &version_parts(b))
+    }
+
+    #[test]
+    fn splits_numbers_and_words() {
+        assert_eq!(version_parts("foo-1.10.0-rc2"),
+                   vec![VersionPart::Text("foo".to_string()),
+                        VersionPart::Number(1),
+                        VersionPart::Number(10),
+                        VersionPart::Number(0),
+                        VersionPart::PreRelease("rc".to_string()),
+                        VersionPart::Number(2)]);
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(compare("file2", "file10"), Ordering::Less);
+        assert_eq!(compare("foo-1.2", "foo-1.10"), Ordering::Less);
+        assert_eq!(compare("file10", "file9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn leading_zeros_are_ignored() {
+        assert_eq!(compare("file007", "file7"), Ordering::Equal);
+        assert_eq!(compare("file010", "file9"), Ordering::Greater);
+    }
+
+    #[test]
+    fn mixed_parts() {
+        assert_eq!(compare("a1b", "a1c"), Ordering::Less);
+        assert_eq!(compare("File1", "file2"), Ordering::Less);
+        assert_eq!(compare("1.0-rc1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0-alpha", "1.0-beta"), Ordering::Less);
+        assert_eq!(compare("1.0", "1.0.1"), Ordering::Less);
+    }
+
+    #[test]
+    fn parses_sort_chains() {
+        assert_eq!(SortKey::parse_chain("name, -size").unwrap(),
+                   vec![SortKey { by: SortBy::Name, reverse: false },
+                        SortKey { by: SortBy::Size, reverse: true }]);
+        assert_eq!(SortKey::parse_chain(" , ").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn rejects_invalid_sort_chains() {
+        assert!(SortKey::parse_chain("name,bogus").is_err());
+        assert!(SortKey::parse_chain("-").is_err());
+        assert!(SortKey::parse_chain("name;size").is_err());
+    }
+}
@@ -0,0 +1,347 @@
+use regex::Regex;
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::files::File;
+use crate::fuzzy::MatchMode;
+use crate::query::Query;
+use crate::fail::HResult;
+
+
+// A single entry of the filter stack. Plain text is matched according
+// to the match_mode, text containing *, ? or [..] is treated as a glob
+// and /.../ as a regex. A leading ! inverts the filter. Queries over
+// file attributes are created with Filter::query().
+#[derive(Clone)]
+pub struct Filter {
+    source: String,
+    negated: bool,
+    kind: FilterKind,
+    // Fuzzy matching every name on each get_files() is too slow, so
+    // results are kept for as long as the filter is used
+    matched: Arc<RwLock<HashMap<(MatchMode, bool, String), bool>>>
+}
+
+#[derive(Clone)]
+enum FilterKind {
+    Substring(String),
+    Glob(Vec<char>),
+    Regex(Regex),
+    Query(Query)
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> HResult<Filter> {
+        let (negated, pattern) = if source.starts_with("!") && source.len() > 1 {
+            (true, &source[1..])
+        } else { (false, source) };
+
+        let kind = if pattern.len() > 1 &&
+            pattern.starts_with("/") &&
+            pattern.ends_with("/") {
+            FilterKind::Regex(Regex::new(&pattern[1..pattern.len()-1])?)
+        } else if pattern.contains(|c| c == '*' || c == '?' || c == '[') {
+            FilterKind::Glob(pattern.chars().collect())
+        } else {
+            FilterKind::Substring(pattern.to_string())
+        };
+
+        Ok(Filter {
+            source: source.to_string(),
+            negated: negated,
+            kind: kind,
+            matched: Arc::new(RwLock::new(HashMap::new()))
+        })
+    }
+
+    pub fn query(source: &str) -> HResult<Filter> {
+        Ok(Filter {
+            source: source.to_string(),
+            negated: false,
+            kind: FilterKind::Query(Query::parse(source)?),
+            matched: Arc::new(RwLock::new(HashMap::new()))
+        })
+    }
+
+    pub fn is_query(&self) -> bool {
+        match self.kind {
+            FilterKind::Query(_) => true,
+            _ => false
+        }
+    }
+
+    // Plain text is only matched case insensitively with smart_case
+    // and a pattern without uppercase chars
+    pub fn matches(&self, file: &File, match_mode: MatchMode, smart_case: bool) -> bool {
+        let name = &file.name;
+        let matched = match &self.kind {
+            FilterKind::Substring(pattern) => {
+                self.text_matches(pattern, name, match_mode, smart_case)
+            }
+            FilterKind::Glob(pattern) => {
+                let name = name.chars().collect::<Vec<char>>();
+                glob_match(pattern, &name)
+            }
+            FilterKind::Regex(regex) => regex.is_match(name),
+            FilterKind::Query(query) => query.matches(file)
+        };
+
+        matched != self.negated
+    }
+
+    fn text_matches(&self,
+                    pattern: &str,
+                    name: &str,
+                    match_mode: MatchMode,
+                    smart_case: bool) -> bool {
+        let key = (match_mode, smart_case, name.to_string());
+        let cached = self.matched.read()
+            .ok()
+            .and_then(|matched| matched.get(&key).cloned());
+
+        cached.unwrap_or_else(|| {
+            let matched = match_mode.matches_case(pattern, name, smart_case);
+            self.matched.write().map(|mut cache| cache.insert(key, matched)).ok();
+            matched
+        })
+    }
+
+    // Chars of name to highlight, only makes sense for positive filters
+    pub fn positions(&self,
+                     name: &str,
+                     match_mode: MatchMode,
+                     smart_case: bool) -> Vec<usize> {
+        if self.negated { return vec![] }
+
+        match &self.kind {
+            FilterKind::Substring(pattern) => {
+                match_mode.positions_case(pattern, name, smart_case)
+            }
+            FilterKind::Regex(regex) => {
+                regex.find(name)
+                    .map(|found| {
+                        let start = name[..found.start()].chars().count();
+                        let len = found.as_str().chars().count();
+                        (start..start + len).collect()
+                    })
+                    .unwrap_or_default()
+            }
+            FilterKind::Glob(_) | FilterKind::Query(_) => vec![]
+        }
+    }
+}
+
+// Filters are equal if they were written the same way, this keeps
+// Files comparable even though Regex isn't
+impl PartialEq for Filter {
+    fn eq(&self, other: &Filter) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Eq for Filter {}
+
+impl std::hash::Hash for Filter {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
+
+impl std::fmt::Debug for Filter {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(formatter, "Filter({:?})", self.source)
+    }
+}
+
+impl std::fmt::Display for Filter {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(formatter, "{}", self.source)
+    }
+}
+
+// Shell style matching of the whole name, without any special
+// treatment of leading dots. On a mismatch only the last star is
+// retried with one more char, which is enough and keeps this linear
+// for patterns like "*a*a*a*b".
+pub fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Position after the last star and where in text it started
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        let ch = text[t];
+        let advance = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => match match_class(&pattern[p+1..], ch) {
+                Some((true, len)) => Some(1 + len),
+                Some((false, _)) => None,
+                // No closing bracket, so it's just a normal char
+                None if ch == '[' => Some(1),
+                None => None
+            },
+            Some(&c) if c == ch => Some(1),
+            _ => None
+        };
+
+        match (advance, star) {
+            (Some(len), _) => { p += len; t += 1; }
+            (None, Some((star_p, star_t))) => {
+                // Let the star swallow one more char
+                star = Some((star_p, star_t + 1));
+                p = star_p;
+                t = star_t + 1;
+            }
+            (None, None) => return false
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// Matches ch against a [...] class, pattern starts right after the
+// opening bracket. Returns the result and the length of the class
+fn match_class(pattern: &[char], ch: char) -> Option<(bool, usize)> {
+    let mut pos = 0;
+    let negated = match pattern.first() {
+        Some('!') | Some('^') => { pos += 1; true }
+        _ => false
+    };
+    let start = pos;
+    let mut matched = false;
+
+    loop {
+        let c = *pattern.get(pos)?;
+        // A ] right at the start is part of the class
+        if c == ']' && pos > start { break; }
+
+        let range_end = match pattern.get(pos + 1) {
+            Some('-') => pattern.get(pos + 2).filter(|&&end| end != ']').cloned(),
+            _ => None
+        };
+
+        match range_end {
+            Some(end) => {
+                if c <= ch && ch <= end { matched = true; }
+                pos += 3;
+            }
+            None => {
+                if c == ch { matched = true; }
+                pos += 1;
+            }
+        }
+    }
+
+    Some((matched != negated, pos + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_match, match_class};
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        let pattern = pattern.chars().collect::<Vec<char>>();
+        let text = text.chars().collect::<Vec<char>>();
+        glob_match(&pattern, &text)
+    }
+
+    #[test]
+    fn glob_literal() {
+        assert!(glob("foo.rs", "foo.rs"));
+        assert!(!glob("foo.rs", "foo.rsx"));
+        assert!(!glob("foo.rs", "foo"));
+        assert!(glob("", ""));
+        assert!(!glob("", "a"));
+    }
+
+    #[test]
+    fn glob_star() {
+        assert!(glob("*.rs", "main.rs"));
+        assert!(glob("*.rs", ".rs"));
+        assert!(!glob("*.rs", "main.rs.bak"));
+        assert!(glob("*", ""));
+        assert!(glob("**", "anything"));
+        assert!(glob("a*b*c", "aXXbYYc"));
+        assert!(glob("a*b*c", "abcbc"));
+        assert!(!glob("a*b*c", "aXXbYY"));
+        assert!(glob("*.tar.*", "x.tar.gz"));
+    }
+
+    #[test]
+    fn glob_question_mark() {
+        assert!(glob("?.rs", "a.rs"));
+        assert!(!glob("?.rs", ".rs"));
+        assert!(!glob("?", ""));
+        assert!(glob("*?", "a"));
+        assert!(!glob("*?", ""));
+    }
+
+    #[test]
+    fn glob_class() {
+        assert!(glob("[abc].rs", "b.rs"));
+        assert!(!glob("[abc].rs", "d.rs"));
+        assert!(glob("file[0-9]", "file7"));
+        assert!(!glob("file[0-9]", "filex"));
+        assert!(glob("[!a]*", "bcd"));
+        assert!(!glob("[!a]*", "abc"));
+        assert!(glob("*[.]rs", "main.rs"));
+        // Unclosed brackets are plain chars
+        assert!(glob("[abc", "[abc"));
+        assert!(!glob("[abc", "a"));
+    }
+
+    #[test]
+    fn glob_many_stars_is_fast() {
+        let text = "a".repeat(100);
+        assert!(!glob("*a*a*a*a*a*a*a*a*a*a*b", &text));
+        assert!(glob("*a*a*a*a*a*a*a*a*a*a*", &text));
+    }
+
+    #[test]
+    fn class_single_chars() {
+        let class = "abc]".chars().collect::<Vec<char>>();
+        assert_eq!(match_class(&class, 'b'), Some((true, 4)));
+        assert_eq!(match_class(&class, 'x'), Some((false, 4)));
+    }
+
+    #[test]
+    fn class_ranges() {
+        let class = "a-z0-9]".chars().collect::<Vec<char>>();
+        assert_eq!(match_class(&class, 'q'), Some((true, 7)));
+        assert_eq!(match_class(&class, '5'), Some((true, 7)));
+        assert_eq!(match_class(&class, 'Q'), Some((false, 7)));
+
+        // A - before the closing bracket is just a char
+        let class = "a-]".chars().collect::<Vec<char>>();
+        assert_eq!(match_class(&class, '-'), Some((true, 3)));
+        assert_eq!(match_class(&class, 'b'), Some((false, 3)));
+    }
+
+    #[test]
+    fn class_negated() {
+        let class = "!abc]".chars().collect::<Vec<char>>();
+        assert_eq!(match_class(&class, 'x'), Some((true, 5)));
+        assert_eq!(match_class(&class, 'a'), Some((false, 5)));
+
+        let class = "^0-9]".chars().collect::<Vec<char>>();
+        assert_eq!(match_class(&class, '5'), Some((false, 5)));
+    }
+
+    #[test]
+    fn class_leading_bracket() {
+        let class = "]a]".chars().collect::<Vec<char>>();
+        assert_eq!(match_class(&class, ']'), Some((true, 3)));
+        assert_eq!(match_class(&class, 'a'), Some((true, 3)));
+    }
+
+    #[test]
+    fn class_unclosed() {
+        let class = "abc".chars().collect::<Vec<char>>();
+        assert_eq!(match_class(&class, 'a'), None);
+    }
+}
@@ -0,0 +1,227 @@
+use async_value::Stale;
+
+use std::collections::BinaryHeap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+
+use crate::fail::ErrorLog;
+use crate::fuzzy::MatchMode;
+use crate::widget::Events;
+use crate::term;
+
+
+// Better matches sort first: a higher score, a match in the file name
+// itself instead of the dirs, then a shorter path
+type Rank = (i64, bool, usize, PathBuf);
+
+// Collects all paths below root in the background, so they can be
+// searched while the traversal is still running
+pub struct Finder {
+    root: PathBuf,
+    paths: Arc<RwLock<Vec<PathBuf>>>,
+    done: Arc<AtomicBool>,
+    stale: Stale,
+    match_mode: MatchMode,
+    smart_case: bool,
+    // Query, limit, how many paths were searched and the best matches
+    last: Option<(String, usize, usize, Vec<Rank>)>,
+}
+
+impl Finder {
+    pub fn new(root: &Path,
+               show_hidden: bool,
+               match_mode: MatchMode,
+               smart_case: bool) -> Finder {
+        let finder = Finder {
+            root: root.to_path_buf(),
+            paths: Arc::new(RwLock::new(vec![])),
+            done: Arc::new(AtomicBool::new(false)),
+            stale: Stale::new(),
+            match_mode: match_mode,
+            smart_case: smart_case,
+            last: None
+        };
+
+        let root = finder.root.clone();
+        let paths = finder.paths.clone();
+        let done = finder.done.clone();
+        let stale = finder.stale.clone();
+
+        rayon::spawn(move || {
+            walk_dir(&root, &paths, &stale, show_hidden);
+            done.store(true, Ordering::Relaxed);
+        });
+
+        finder
+    }
+
+    pub fn find(&mut self, query: &str, limit: usize) -> Vec<PathBuf> {
+        let paths = match self.paths.read() {
+            Ok(paths) => paths,
+            Err(_) => return vec![]
+        };
+
+        // Paths are only ever added, so with the same query just the new
+        // ones have to be looked at
+        let (searched, best) = match self.last.take() {
+            Some((last_query, last_limit, searched, best))
+                if last_query == query && last_limit == limit => (searched, best),
+            _ => (0, vec![])
+        };
+
+        // Keeps only the best matches, with the worst one on top
+        let mut heap = best.into_iter().collect::<BinaryHeap<Rank>>();
+
+        for path in &paths[searched..] {
+            let (score, dir_match, len) = match rank(path,
+                                                     &self.root,
+                                                     query,
+                                                     self.match_mode,
+                                                     self.smart_case) {
+                Some(rank) => rank,
+                None => continue
+            };
+
+            if heap.len() >= limit {
+                match heap.peek() {
+                    Some(worst) if (score, dir_match, len) > (worst.0, worst.1, worst.2) => continue,
+                    None => continue,
+                    _ => {}
+                }
+            }
+
+            heap.push((score, dir_match, len, path.clone()));
+            if heap.len() > limit { heap.pop(); }
+        }
+
+        let best = heap.into_sorted_vec();
+        let results = best.iter()
+            .map(|(_, _, _, path)| path.clone())
+            .collect();
+
+        self.last = Some((query.to_string(), limit, paths.len(), best));
+        results
+    }
+
+    // Asks for new results every now and then while the traversal is
+    // running, so the list doesn't only change on key presses
+    pub fn refresh_while_walking(&self, sender: Sender<Events>) {
+        let done = self.done.clone();
+        let stale = self.stale.clone();
+        let pause = std::time::Duration::from_millis(250);
+
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(pause);
+                if stale.is_stale().unwrap_or(true) { break; }
+
+                let finished = done.load(Ordering::Relaxed);
+                sender.send(Events::InputUpdated(String::new())).ok();
+                if finished { break; }
+            }
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.read().map(|paths| paths.len()).unwrap_or(0)
+    }
+
+    pub fn render(&self, results: &[PathBuf], selected: usize, lines: usize) -> String {
+        let xsize = term::xsize();
+        let ysize = term::ysize() as usize;
+        // Keep the minibuffer and status line free
+        let ypos = ysize.saturating_sub(lines + 2);
+
+        let header = format!("{} / {} files", results.len(), self.len());
+        let header = format!("{}{}{}{}",
+                             term::goto_xy_u(1, ypos),
+                             term::header_color(),
+                             term::sized_string(&header, xsize),
+                             term::reset());
+
+        let lines = (0..lines).map(|i| {
+            let line = results.get(i)
+                .map(|path| path.strip_prefix(&self.root).unwrap_or(path))
+                .map(|path| path.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let highlight = if i == selected && !line.is_empty() {
+                term::invert()
+            } else { String::new() };
+
+            format!("{}{}{}{}{}",
+                    term::goto_xy_u(1, ypos + i + 1),
+                    term::reset(),
+                    highlight,
+                    term::sized_string(&line, xsize),
+                    term::reset())
+        }).collect::<String>();
+
+        header + &lines
+    }
+}
+
+impl Drop for Finder {
+    fn drop(&mut self) {
+        // Stop the traversal when it's not needed anymore
+        self.stale.set_stale().log();
+    }
+}
+
+fn rank(path: &Path,
+        root: &Path,
+        query: &str,
+        match_mode: MatchMode,
+        smart_case: bool) -> Option<(i64, bool, usize)> {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    let relative = relative.to_string_lossy();
+    let score = match_mode.score_case(query, &relative, smart_case)?;
+
+    let name_match = path.file_name()
+        .map(|name| match_mode.matches_case(query,
+                                            &name.to_string_lossy(),
+                                            smart_case))
+        .unwrap_or(false);
+
+    // Negated, so that higher scores sort first
+    Some((-score, !name_match, relative.chars().count()))
+}
+
+fn walk_dir(dir: &Path,
+            paths: &Arc<RwLock<Vec<PathBuf>>>,
+            stale: &Stale,
+            show_hidden: bool) {
+    if stale.is_stale().unwrap_or(true) { return; }
+
+    let direntries = match std::fs::read_dir(dir) {
+        Ok(direntries) => direntries,
+        Err(_) => return
+    };
+
+    let mut found = vec![];
+    let mut dirs = vec![];
+
+    for direntry in direntries.filter_map(|direntry| direntry.ok()) {
+        let hidden = direntry.file_name().to_string_lossy().starts_with(".");
+        if hidden && !show_hidden { continue; }
+
+        let path = direntry.path();
+
+        // Symlinks aren't followed, so there can't be any loops
+        if direntry.file_type().map(|kind| kind.is_dir()).unwrap_or(false) {
+            dirs.push(path.clone());
+        }
+
+        found.push(path);
+    }
+
+    paths.write().map(|mut paths| paths.extend(found)).ok();
+
+    rayon::scope(|scope| {
+        for dir in dirs {
+            scope.spawn(move |_| walk_dir(&dir, paths, stale, show_hidden));
+        }
+    });
+}
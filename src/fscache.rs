@@ -2,13 +2,14 @@ use notify::{RecommendedWatcher, Watcher, DebouncedEvent, RecursiveMode};
 
 use async_value::{Async, Stale};
 
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, RwLock, Mutex};
 use std::sync::mpsc::{channel, Sender, Receiver};
 use std::collections::{HashMap, HashSet};
 use std::time::Duration;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::files::{Files, File, SortBy};
+use crate::files::{Files, File, SortBy, SortKey};
+use crate::filter::Filter;
 use crate::widget::Events;
 use crate::fail::{HResult, HError, ErrorLog, Backtrace, ArcBacktrace};
 
@@ -16,22 +17,43 @@ use crate::fail::{HResult, HError, ErrorLog, Backtrace, ArcBacktrace};
 #[derive(Debug, Clone)]
 pub struct DirSettings {
     sort: SortBy,
+    sort_reverse: bool,
+    case_insensitive: bool,
+    // Random sorting stays the same when coming back
+    random_seed: u64,
+    sort_chain: Option<Vec<SortKey>>,
     dirs_first: bool,
     reverse: bool,
     show_hidden: bool,
-    filter: Option<String>,
+    filters: Vec<Filter>,
 }
 
 impl DirSettings {
     fn new() -> DirSettings {
         DirSettings {
             sort: SortBy::Name,
+            sort_reverse: false,
+            case_insensitive: false,
+            random_seed: 0,
+            sort_chain: None,
             dirs_first: true,
             reverse: false,
             show_hidden: true,
-            filter: None
+            filters: vec![]
         }
     }
+
+    fn is_default(&self) -> bool {
+        self.sort == SortBy::Name &&
+            !self.sort_reverse &&
+            !self.case_insensitive &&
+            self.random_seed == 0 &&
+            self.sort_chain.is_none() &&
+            self.dirs_first &&
+            !self.reverse &&
+            self.show_hidden &&
+            self.filters.is_empty()
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -49,6 +71,17 @@ impl TabSettings {
             dir_settings: DirSettings::new()
         }
     }
+
+    // Only the cursor position isn't worth keeping around
+    fn is_default(&self) -> bool {
+        self.multi_selections.is_empty() && self.dir_settings.is_default()
+    }
+}
+
+lazy_static! {
+    // Read once, every browser starts with a copy
+    static ref DIR_SETTINGS: Mutex<HashMap<File, TabSettings>> =
+        Mutex::new(FsCache::load_dir_settings());
 }
 
 
@@ -84,7 +117,7 @@ impl FsCache {
 
         let fs_cache = FsCache {
             files: Arc::new(RwLock::new(HashMap::new())),
-            tab_settings: Arc::new(RwLock::new(HashMap::new())),
+            tab_settings: Arc::new(RwLock::new(FsCache::saved_dir_settings())),
             watched_dirs: Arc::new(RwLock::new(HashSet::new())),
             watcher: Arc::new(RwLock::new(watcher)),
             fs_changes: Arc::new(RwLock::new(vec![])),
@@ -139,6 +172,18 @@ impl FsCache {
         Ok(self.tab_settings.read()?.get(&dir).as_ref()?.selection.as_ref()?.clone())
     }
 
+    // Makes dir come up with these files selected when it's opened
+    pub fn set_selections(&self,
+                          dir: &File,
+                          selection: Option<File>,
+                          multi_selections: Vec<File>) -> HResult<()> {
+        let mut tab_settings = self.tab_settings.write()?;
+        let tab_settings = tab_settings.entry(dir.clone()).or_insert_with(TabSettings::new);
+        tab_settings.selection = selection;
+        tab_settings.multi_selections = multi_selections;
+        Ok(())
+    }
+
     pub fn save_settings(&self, files: &Files, selection: Option<File>) -> HResult<()> {
         let dir = files.directory.clone();
         let tab_settings = FsCache::extract_tab_settings(&files, selection);
@@ -220,10 +265,14 @@ impl FsCache {
             let tab_settings = &tab_settings;
 
             files.sort = tab_settings.dir_settings.sort;
+            files.sort_reverse = tab_settings.dir_settings.sort_reverse;
+            files.case_insensitive = tab_settings.dir_settings.case_insensitive;
+            files.random_seed = tab_settings.dir_settings.random_seed;
+            files.sort_chain = tab_settings.dir_settings.sort_chain.clone();
             files.dirs_first = tab_settings.dir_settings.dirs_first;
             files.reverse = tab_settings.dir_settings.reverse;
             files.show_hidden = tab_settings.dir_settings.show_hidden;
-            files.filter = tab_settings.dir_settings.filter.clone();
+            files.filters = tab_settings.dir_settings.filters.clone();
 
             if tab_settings.multi_selections.len() > 0 {
                 for file in &mut files.files {
@@ -253,10 +302,14 @@ impl FsCache {
         let tab_settings = tab_settings?;
 
         files.sort = tab_settings.dir_settings.sort;
+        files.sort_reverse = tab_settings.dir_settings.sort_reverse;
+        files.case_insensitive = tab_settings.dir_settings.case_insensitive;
+        files.random_seed = tab_settings.dir_settings.random_seed;
+        files.sort_chain = tab_settings.dir_settings.sort_chain.clone();
         files.dirs_first = tab_settings.dir_settings.dirs_first;
         files.reverse = tab_settings.dir_settings.reverse;
         files.show_hidden = tab_settings.dir_settings.show_hidden;
-        files.filter = tab_settings.dir_settings.filter.clone();
+        files.filters = tab_settings.dir_settings.filters.clone();
 
         if tab_settings.multi_selections.len() > 0 {
             for file in &mut files.files {
@@ -272,6 +325,61 @@ impl FsCache {
         Ok(())
     }
 
+    // Settings of visited directories are kept across sessions in a
+    // simple "key value" format, with an empty line after each dir
+    fn load_dir_settings() -> HashMap<File, TabSettings> {
+        let content = crate::paths::dirsettings_path()
+            .and_then(|path| Ok(std::fs::read_to_string(path)?));
+
+        match content {
+            Ok(content) => parse_dir_settings(&content),
+            Err(_) => HashMap::new()
+        }
+    }
+
+    fn saved_dir_settings() -> HashMap<File, TabSettings> {
+        DIR_SETTINGS.lock()
+            .map(|settings| settings.clone())
+            .unwrap_or_else(|_| HashMap::new())
+    }
+
+    // Another instance might have saved in the meantime, so its
+    // directories are kept unless they were changed here, too
+    pub fn save_dir_settings(settings: &HashMap<File, TabSettings>,
+                             prune: bool) -> HResult<()> {
+        let path = crate::paths::dirsettings_path()?;
+        let mut merged = FsCache::load_dir_settings();
+
+        for (dir, tab_settings) in settings {
+            if tab_settings.is_default() {
+                merged.remove(dir);
+            } else {
+                merged.insert(dir.clone(), tab_settings.clone());
+            }
+        }
+
+        let mut dirs = merged
+            .iter()
+            .filter(|(dir, _)| !prune || dir.path.exists())
+            .collect::<Vec<_>>();
+        dirs.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+
+        let content = dirs
+            .into_iter()
+            .filter_map(|(dir, tab_settings)| dir_settings_string(dir, tab_settings))
+            .collect::<String>();
+
+        // Renaming is atomic, so a crash can't leave half a file behind
+        let tmp_path = path.with_extension(format!("tmp.{}", std::process::id()));
+        std::fs::write(&tmp_path, content)?;
+        std::fs::rename(&tmp_path, &path)?;
+
+        if let Ok(mut saved) = DIR_SETTINGS.lock() {
+            *saved = merged;
+        }
+        Ok(())
+    }
+
     pub fn ensure_not_empty(mut files: Files) -> HResult<Files> {
         if files.len() == 0 {
             let path = &files.directory.path;
@@ -288,10 +396,14 @@ impl FsCache {
             multi_selections: files.get_selected().into_iter().cloned().collect(),
             dir_settings: DirSettings {
                 sort: files.sort,
+                sort_reverse: files.sort_reverse,
+                case_insensitive: files.case_insensitive,
+                random_seed: files.random_seed,
+                sort_chain: files.sort_chain.clone(),
                 dirs_first: files.dirs_first,
                 reverse: files.reverse,
                 show_hidden: files.show_hidden,
-                filter: files.filter.clone(),
+                filters: files.filters.clone(),
             }
         }
     }
@@ -382,3 +494,200 @@ impl PathFromEvent for DebouncedEvent {
         }
     }
 }
+
+fn on_off(value: bool) -> &'static str {
+    if value { "on" } else { "off" }
+}
+
+// Paths with newlines can't be saved in this format, so they're skipped
+fn line_safe_path(file: &File) -> Option<String> {
+    let path = file.path.to_string_lossy().to_string();
+    if path.contains("\n") { None } else { Some(path) }
+}
+
+fn dir_settings_string(dir: &File, tab_settings: &TabSettings) -> Option<String> {
+    let dir_settings = &tab_settings.dir_settings;
+    let mut lines = vec![format!("dir {}", line_safe_path(dir)?)];
+
+    let sort = SortKey { by: dir_settings.sort, reverse: dir_settings.sort_reverse };
+    lines.push(format!("sort {}", sort));
+    // An empty chain is written, too, so it isn't replaced by the config's
+    if let Some(chain) = &dir_settings.sort_chain {
+        let chain = chain
+            .iter()
+            .map(|key| key.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        lines.push(format!("sort_chain {}", chain));
+    }
+    if dir_settings.random_seed != 0 {
+        lines.push(format!("random_seed {}", dir_settings.random_seed));
+    }
+    lines.push(format!("case_insensitive {}", on_off(dir_settings.case_insensitive)));
+    lines.push(format!("dirs_first {}", on_off(dir_settings.dirs_first)));
+    lines.push(format!("reverse {}", on_off(dir_settings.reverse)));
+    lines.push(format!("show_hidden {}", on_off(dir_settings.show_hidden)));
+
+    for filter in &dir_settings.filters {
+        let filter_str = filter.to_string();
+        if filter_str.contains("\n") { continue; }
+
+        let key = if filter.is_query() { "query" } else { "filter" };
+        lines.push(format!("{} {}", key, filter_str));
+    }
+
+    if let Some(selection) = tab_settings.selection.as_ref().and_then(line_safe_path) {
+        lines.push(format!("selection {}", selection));
+    }
+
+    for selected in tab_settings.multi_selections.iter().filter_map(line_safe_path) {
+        lines.push(format!("selected {}", selected));
+    }
+
+    Some(lines.join("\n") + "\n\n")
+}
+
+fn parse_dir_settings(content: &str) -> HashMap<File, TabSettings> {
+    let mut settings = HashMap::new();
+    let mut current: Option<(File, TabSettings)> = None;
+
+    for line in content.lines() {
+        let mut parts = line.splitn(2, " ");
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue
+        };
+
+        if key == "dir" {
+            if let Some((dir, tab_settings)) = current.take() {
+                settings.insert(dir, tab_settings);
+            }
+            current = File::new_from_path(Path::new(value), None)
+                .ok()
+                .map(|dir| (dir, TabSettings::new()));
+            continue;
+        }
+
+        let tab_settings = match current.as_mut() {
+            Some((_, tab_settings)) => tab_settings,
+            None => continue
+        };
+        let dir_settings = &mut tab_settings.dir_settings;
+
+        match key {
+            "sort" => {
+                value.parse::<SortKey>()
+                    .map(|sort| {
+                        dir_settings.sort = sort.by;
+                        dir_settings.sort_reverse = sort.reverse;
+                    })
+                    .log();
+            }
+            "sort_chain" => {
+                SortKey::parse_chain(value)
+                    .map(|chain| dir_settings.sort_chain = Some(chain))
+                    .log();
+            }
+            "random_seed" => {
+                if let Ok(seed) = value.parse() {
+                    dir_settings.random_seed = seed;
+                }
+            }
+            "case_insensitive" => dir_settings.case_insensitive = value == "on",
+            "dirs_first" => dir_settings.dirs_first = value == "on",
+            "reverse" => dir_settings.reverse = value == "on",
+            "show_hidden" => dir_settings.show_hidden = value == "on",
+            "filter" => {
+                Filter::parse(value).map(|filter| dir_settings.filters.push(filter)).log();
+            }
+            "query" => {
+                Filter::query(value).map(|filter| dir_settings.filters.push(filter)).log();
+            }
+            "selection" => {
+                tab_settings.selection = File::new_from_path(Path::new(value), None).ok();
+            }
+            "selected" => {
+                File::new_from_path(Path::new(value), None)
+                    .map(|file| tab_settings.multi_selections.push(file))
+                    .log();
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((dir, tab_settings)) = current.take() {
+        settings.insert(dir, tab_settings);
+    }
+
+    settings
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{dir_settings_string, parse_dir_settings, DirSettings, TabSettings};
+    use crate::files::{File, SortBy, SortKey};
+    use crate::filter::Filter;
+    use std::path::Path;
+
+    fn file(path: &str) -> File {
+        File::new_from_path(Path::new(path), None).unwrap()
+    }
+
+    fn tab_settings() -> TabSettings {
+        let mut dir_settings = DirSettings::new();
+        dir_settings.sort = SortBy::Size;
+        dir_settings.sort_reverse = true;
+        dir_settings.sort_chain = Some(vec![SortKey { by: SortBy::MTime, reverse: true }]);
+        dir_settings.random_seed = 42;
+        dir_settings.show_hidden = false;
+        dir_settings.filters = vec![Filter::parse("*.rs").unwrap()];
+
+        TabSettings {
+            selection: Some(file("/tmp/some dir/a file.rs")),
+            multi_selections: vec![file("/tmp/some dir/a file.rs"),
+                                   file("/tmp/some dir/other file")],
+            dir_settings: dir_settings
+        }
+    }
+
+    #[test]
+    fn round_trips_paths_with_spaces() {
+        let dir = file("/tmp/some dir");
+        let content = dir_settings_string(&dir, &tab_settings()).unwrap();
+
+        let settings = parse_dir_settings(&content);
+        let parsed = settings.get(&dir).unwrap();
+
+        assert_eq!(parsed.selection, Some(file("/tmp/some dir/a file.rs")));
+        assert_eq!(parsed.multi_selections.len(), 2);
+        assert_eq!(parsed.dir_settings.sort, SortBy::Size);
+        assert!(parsed.dir_settings.sort_reverse);
+        assert_eq!(parsed.dir_settings.random_seed, 42);
+        assert!(!parsed.dir_settings.show_hidden);
+        assert_eq!(dir_settings_string(&dir, parsed), Some(content));
+    }
+
+    #[test]
+    fn skips_unknown_keys() {
+        let dir = file("/tmp/some dir");
+        let content = dir_settings_string(&dir, &tab_settings()).unwrap();
+        let with_unknown = content.replacen("\n", "\nsomething_new on\nbogus\n", 1);
+
+        let settings = parse_dir_settings(&with_unknown);
+        let parsed = settings.get(&dir).unwrap();
+
+        assert_eq!(settings.len(), 1);
+        assert_eq!(dir_settings_string(&dir, parsed), Some(content));
+    }
+
+    #[test]
+    fn ignores_settings_before_the_first_dir() {
+        let settings = parse_dir_settings("sort size\nshow_hidden off\n\ndir /tmp\nreverse on\n");
+        let parsed = settings.get(&file("/tmp")).unwrap();
+
+        assert_eq!(settings.len(), 1);
+        assert_eq!(parsed.dir_settings.sort, SortBy::Name);
+        assert!(parsed.dir_settings.reverse);
+    }
+}
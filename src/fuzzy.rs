@@ -0,0 +1,194 @@
+const SCORE_MATCH: i64 = 16;
+const BONUS_CONSECUTIVE: i64 = 16;
+const BONUS_BOUNDARY: i64 = 24;
+const PENALTY_GAP: i64 = 1;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MatchMode {
+    Substring,
+    Fuzzy
+}
+
+impl MatchMode {
+    // Both modes ignore case, unless the pattern contains uppercase chars
+    pub fn score(&self, pattern: &str, text: &str) -> Option<i64> {
+        self.score_case(pattern, text, true)
+    }
+
+    // Without smart_case the case always has to match
+    pub fn score_case(&self, pattern: &str, text: &str, smart_case: bool) -> Option<i64> {
+        match self {
+            MatchMode::Substring => substring_match(pattern, text, smart_case).map(|_| 0),
+            MatchMode::Fuzzy => fuzzy_match(pattern, text, smart_case).map(|(score, _)| score)
+        }
+    }
+
+    pub fn matches(&self, pattern: &str, text: &str) -> bool {
+        self.score(pattern, text).is_some()
+    }
+
+    pub fn matches_case(&self, pattern: &str, text: &str, smart_case: bool) -> bool {
+        self.score_case(pattern, text, smart_case).is_some()
+    }
+
+    // Positions of the matched chars, for highlighting
+    pub fn positions(&self, pattern: &str, text: &str) -> Vec<usize> {
+        self.positions_case(pattern, text, true)
+    }
+
+    pub fn positions_case(&self, pattern: &str, text: &str, smart_case: bool) -> Vec<usize> {
+        match self {
+            MatchMode::Substring => substring_match(pattern, text, smart_case),
+            MatchMode::Fuzzy => fuzzy_match(pattern, text, smart_case).map(|(_, pos)| pos)
+        }.unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for MatchMode {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        let text = match self {
+            MatchMode::Substring => "substring",
+            MatchMode::Fuzzy => "fuzzy",
+        };
+        write!(formatter, "{}", text)
+    }
+}
+
+fn prepare(pattern: &str, text: &str, smart_case: bool) -> (Vec<char>, Vec<char>) {
+    let case_sensitive = !smart_case || pattern.chars().any(|c| c.is_uppercase());
+    let normalize = |c: char| {
+        if case_sensitive { c }
+        else { c.to_lowercase().next().unwrap_or(c) }
+    };
+
+    (pattern.chars().map(normalize).collect(),
+     text.chars().map(normalize).collect())
+}
+
+fn substring_match(pattern: &str, text: &str, smart_case: bool) -> Option<Vec<usize>> {
+    let (pattern, text) = prepare(pattern, text, smart_case);
+    if pattern.is_empty() { return Some(vec![]) }
+
+    let start = text.windows(pattern.len())
+        .position(|window| window == &pattern[..])?;
+
+    Some((start..start + pattern.len()).collect())
+}
+
+pub fn fuzzy_match(pattern: &str,
+                   text: &str,
+                   smart_case: bool) -> Option<(i64, Vec<usize>)> {
+    let chars = text.chars().collect::<Vec<char>>();
+    let (pattern, text) = prepare(pattern, text, smart_case);
+    if pattern.is_empty() { return Some((0, vec![])) }
+
+    // Try every occurrence of the first char and keep the best result
+    text.iter()
+        .enumerate()
+        .filter(|(_, c)| **c == pattern[0])
+        .filter_map(|(start, _)| match_from(&pattern, &text, &chars, start))
+        .fold(None, |best: Option<(i64, Vec<usize>)>, current| {
+            match best {
+                Some(ref best) if best.0 >= current.0 => Some(best.clone()),
+                _ => Some(current)
+            }
+        })
+}
+
+fn match_from(pattern: &[char],
+              text: &[char],
+              chars: &[char],
+              start: usize) -> Option<(i64, Vec<usize>)> {
+    let mut positions: Vec<usize> = Vec::with_capacity(pattern.len());
+    let mut score = 0;
+    let mut pos = start;
+
+    for pattern_char in pattern {
+        let found = (pos..text.len()).find(|&i| text[i] == *pattern_char)?;
+
+        score += SCORE_MATCH;
+
+        if let Some(&prev) = positions.last() {
+            if found == prev + 1 {
+                score += BONUS_CONSECUTIVE;
+            } else {
+                score -= PENALTY_GAP * (found - prev - 1) as i64;
+            }
+        }
+
+        if is_boundary(chars, found) {
+            score += BONUS_BOUNDARY;
+        }
+
+        positions.push(found);
+        pos = found + 1;
+    }
+
+    Some((score, positions))
+}
+
+fn is_boundary(chars: &[char], pos: usize) -> bool {
+    if pos == 0 { return true }
+
+    let prev = chars[pos - 1];
+    match prev {
+        '/' | '_' | '-' | '.' | ' ' => true,
+        _ => prev.is_lowercase() && chars[pos].is_uppercase()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::MatchMode;
+
+    #[test]
+    fn substring_needs_contiguous_chars() {
+        assert!(MatchMode::Substring.matches("bar", "foobar"));
+        assert!(!MatchMode::Substring.matches("fbr", "foobar"));
+        assert_eq!(MatchMode::Substring.positions("oba", "foobar"), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn fuzzy_matches_chars_in_order() {
+        assert!(MatchMode::Fuzzy.matches("fbr", "foobar"));
+        assert!(!MatchMode::Fuzzy.matches("rbf", "foobar"));
+        assert_eq!(MatchMode::Fuzzy.positions("fbr", "foobar"), vec![0, 3, 5]);
+    }
+
+    #[test]
+    fn fuzzy_prefers_consecutive_and_boundary_matches() {
+        let consecutive = MatchMode::Fuzzy.score("foo", "foo_bar").unwrap();
+        let gaps = MatchMode::Fuzzy.score("foo", "fxoxo").unwrap();
+        assert!(consecutive > gaps);
+
+        // The boundary match after the dot wins over the earlier one
+        assert_eq!(MatchMode::Fuzzy.positions("rs", "parser.rs"), vec![7, 8]);
+    }
+
+    #[test]
+    fn empty_pattern_matches_everything() {
+        assert!(MatchMode::Substring.matches("", "foo"));
+        assert!(MatchMode::Fuzzy.matches("", "foo"));
+        assert!(MatchMode::Fuzzy.positions("", "foo").is_empty());
+    }
+
+    #[test]
+    fn smart_case_ignores_case_of_lowercase_patterns() {
+        for mode in &[MatchMode::Substring, MatchMode::Fuzzy] {
+            assert!(mode.matches_case("readme", "README.md", true));
+            assert!(mode.matches_case("READ", "README.md", true));
+            assert!(!mode.matches_case("Read", "README.md", true));
+        }
+    }
+
+    #[test]
+    fn without_smart_case_case_has_to_match() {
+        for mode in &[MatchMode::Substring, MatchMode::Fuzzy] {
+            assert!(!mode.matches_case("readme", "README.md", false));
+            assert!(mode.matches_case("README", "README.md", false));
+            assert!(mode.matches_case("md", "README.md", false));
+        }
+    }
+}
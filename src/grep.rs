@@ -0,0 +1,152 @@
+use async_value::Stale;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Sender;
+
+use crate::files::File;
+use crate::dirty::AsyncDirtyBit;
+use crate::widget::Events;
+use crate::fail::HResult;
+
+
+// Longest part of a matching line that's shown
+const MAX_LINE_LEN: usize = 120;
+
+lazy_static! {
+    // Reading files blocks, so keep that away from the global pool
+    static ref GREP_POOL: ThreadPool = ThreadPoolBuilder::new()
+        .num_threads(8)
+        .build()
+        .expect("Failed to create thread pool");
+}
+
+// Searches the contents of all files below root in parallel. Every
+// matching line becomes a File with its line number set
+pub fn grep(root: PathBuf,
+            pattern: String,
+            show_hidden: bool,
+            pending: Arc<Mutex<Vec<File>>>,
+            dirty_meta: AsyncDirtyBit,
+            stale: Stale,
+            sender: Sender<Events>) -> HResult<()> {
+    let grep = Grep {
+        root: root.clone(),
+        pattern: pattern,
+        show_hidden: show_hidden,
+        pending: pending,
+        dirty_meta: dirty_meta,
+        stale: stale,
+        sender: Mutex::new(sender)
+    };
+
+    GREP_POOL.install(|| grep.grep_dir(&root));
+    Ok(())
+}
+
+struct Grep {
+    root: PathBuf,
+    pattern: String,
+    show_hidden: bool,
+    pending: Arc<Mutex<Vec<File>>>,
+    dirty_meta: AsyncDirtyBit,
+    stale: Stale,
+    // Sender isn't Sync, but the scoped threads need to share it
+    sender: Mutex<Sender<Events>>,
+}
+
+impl Grep {
+    fn grep_dir(&self, dir: &Path) {
+        if self.stale.is_stale().unwrap_or(true) { return; }
+
+        let direntries = match std::fs::read_dir(dir) {
+            Ok(direntries) => direntries,
+            Err(_) => return
+        };
+
+        let direntries = direntries
+            .filter_map(|direntry| direntry.ok())
+            .filter(|direntry| {
+                self.show_hidden ||
+                    !direntry.file_name().to_string_lossy().starts_with(".")
+            })
+            .collect::<Vec<_>>();
+
+        rayon::scope(|scope| {
+            for direntry in direntries {
+                let kind = match direntry.file_type() {
+                    Ok(kind) => kind,
+                    Err(_) => continue
+                };
+                let path = direntry.path();
+
+                if kind.is_dir() {
+                    scope.spawn(move |_| self.grep_dir(&path));
+                } else if kind.is_file() {
+                    scope.spawn(move |_| {
+                        match self.grep_file(&path) {
+                            Ok(ref matches) if matches.is_empty() => {},
+                            Ok(mut matches) => {
+                                self.pending
+                                    .lock()
+                                    .map(|mut pending| pending.append(&mut matches))
+                                    .ok();
+                                self.sender
+                                    .lock()
+                                    .map(|sender| sender.send(Events::WidgetReady))
+                                    .ok();
+                            }
+                            // Unreadable files are just skipped
+                            Err(_) => {}
+                        }
+                    });
+                }
+            }
+        });
+    }
+
+    fn grep_file(&self, path: &Path) -> HResult<Vec<File>> {
+        let file = std::fs::File::open(path)?;
+        let mut reader = std::io::BufReader::new(file);
+
+        // Skip binaries, just like grep does
+        if reader.fill_buf()?.contains(&0) {
+            return Ok(vec![]);
+        }
+
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        let relative = relative.to_string_lossy();
+
+        let mut matches = vec![];
+        let mut buffer = vec![];
+        let mut line_num = 0;
+
+        loop {
+            buffer.clear();
+            if reader.read_until(b'\n', &mut buffer)? == 0 { break; }
+            line_num += 1;
+
+            if self.stale.is_stale()? { return Ok(vec![]); }
+
+            let line = String::from_utf8_lossy(&buffer);
+            if !line.contains(&self.pattern) { continue; }
+
+            let text = line.trim()
+                .chars()
+                .take(MAX_LINE_LEN)
+                .collect::<String>()
+                .replace("\t", " ");
+            let name = format!("{}:{}: {}", relative, line_num, text);
+            let mut file = File::new_with_stale(&name,
+                                                path.to_path_buf(),
+                                                Some(self.dirty_meta.clone()),
+                                                self.stale.clone());
+            file.line = Some(line_num);
+            matches.push(file);
+        }
+
+        Ok(matches)
+    }
+}
@@ -4,6 +4,14 @@ use crate::widget::{Widget, WidgetCore};
 use crate::coordinates::{Coordinates, Size, Position};
 use crate::fail::{HResult, HError, ErrorLog};
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Orientation {
+    // Widgets side by side
+    Horizontal,
+    // Widgets on top of each other
+    Vertical
+}
+
 #[derive(PartialEq)]
 pub struct HBox<T: Widget> {
     pub core: WidgetCore,
@@ -11,16 +19,22 @@ pub struct HBox<T: Widget> {
     pub ratios: Option<Vec<usize>>,
     pub zoom_active: bool,
     pub active: Option<usize>,
+    pub orientation: Orientation,
 }
 
 
 impl<T> HBox<T> where T: Widget + PartialEq {
     pub fn new(core: &WidgetCore) -> HBox<T> {
+        HBox::with_orientation(core, Orientation::Horizontal)
+    }
+
+    pub fn with_orientation(core: &WidgetCore, orientation: Orientation) -> HBox<T> {
         HBox { core: core.clone(),
                widgets: vec![],
                ratios: None,
                zoom_active: false,
-               active: None
+               active: None,
+               orientation: orientation
          }
     }
 
@@ -94,7 +108,9 @@ impl<T> HBox<T> where T: Widget + PartialEq {
         let box_coords = self.get_coordinates()?;
         let box_xsize = box_coords.xsize();
         let box_ysize = box_coords.ysize();
+        let box_left = box_coords.top().x();
         let box_top = box_coords.top().y();
+        let orientation = self.orientation;
 
         let ratios = match &self.ratios {
             Some(ratios) => ratios.clone(),
@@ -106,22 +122,33 @@ impl<T> HBox<T> where T: Widget + PartialEq {
             let len = coords.len();
             let gap = if len == 0 { 0 } else { 1 };
 
-            let widget_xsize = box_xsize * ratio / 100;
-            let widget_xpos = if len == 0 {
-                box_coords.top().x()
-            } else {
-                let prev_coords = coords.last().unwrap();
-                let prev_xsize = prev_coords.xsize();
-                let prev_xpos = prev_coords.position().x();
+            // Sizes and positions along the orientation
+            let (box_size, box_start) = match orientation {
+                Orientation::Horizontal => (box_xsize, box_left),
+                Orientation::Vertical => (box_ysize, box_top)
+            };
+
+            let widget_size = box_size * ratio / 100;
+            let widget_pos = match (coords.last(), orientation) {
+                (None, _) => box_start,
+                (Some(prev), Orientation::Horizontal) => {
+                    prev.xsize() + prev.position().x() + gap
+                }
+                (Some(prev), Orientation::Vertical) => {
+                    prev.ysize() + prev.position().y() + gap
+                }
+            };
 
-                prev_xsize + prev_xpos + gap
+            let (size, position) = match orientation {
+                Orientation::Horizontal => ((widget_size, box_ysize),
+                                            (widget_pos, box_top)),
+                Orientation::Vertical => ((box_xsize, widget_size),
+                                          (box_left, widget_pos))
             };
 
             coords.push(Coordinates {
-                size: Size((widget_xsize,
-                            box_ysize)),
-                position: Position((widget_xpos,
-                                   box_top))
+                size: Size(size),
+                position: Position(position)
             });
             coords
         });
@@ -0,0 +1,409 @@
+use termion::event::Key;
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::files::File;
+use crate::term;
+use crate::widget::{Widget, WidgetCore};
+use crate::fail::{HResult, HError};
+
+
+// How much of a binary file is shown in the preview
+pub const PREVIEW_SIZE: usize = 4 * 1024;
+
+const SEARCH_CHUNK: usize = 64 * 1024;
+
+pub fn read_at(path: &Path, offset: usize, len: usize) -> HResult<Vec<u8>> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset as u64))?;
+
+    let mut bytes = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Like "hexdump -C", but with fewer bytes per line if it doesn't fit
+pub fn hexdump(bytes: &[u8], offset: usize, xsize: usize) -> Vec<String> {
+    let width = bytes_per_line(xsize);
+
+    bytes.chunks(width)
+        .enumerate()
+        .map(|(i, chunk)| hexdump_line(chunk, offset + i * width, width, None))
+        .collect()
+}
+
+fn bytes_per_line(xsize: usize) -> usize {
+    let line_len = |width: usize| 13 + width * 4 + (width - 1) / 8;
+
+    [16, 8].iter()
+        .cloned()
+        .find(|width| line_len(*width) < xsize)
+        .unwrap_or(4)
+}
+
+// Bytes in mark, given as (position, length), are shown inverted
+fn hexdump_line(bytes: &[u8],
+                offset: usize,
+                width: usize,
+                mark: Option<(usize, usize)>) -> String {
+    let marked = |pos: usize| {
+        mark.map(|(start, len)| pos >= start && pos < start + len)
+            .unwrap_or(false)
+    };
+    let mut hex = String::new();
+    let mut ascii = String::new();
+
+    for i in 0..width {
+        if i > 0 && i % 8 == 0 { hex.push(' ') }
+
+        let byte = match bytes.get(i) {
+            Some(byte) => *byte,
+            None => { hex += "   "; continue; }
+        };
+        let ch = if byte.is_ascii_graphic() || byte == b' ' {
+            byte as char
+        } else { '.' };
+
+        if marked(offset + i) {
+            hex += &format!("{}{:02x}{} ", term::invert(), byte, term::reset());
+            ascii += &format!("{}{}{}", term::invert(), ch, term::reset());
+        } else {
+            hex += &format!("{:02x} ", byte);
+            ascii.push(ch);
+        }
+    }
+
+    format!("{:08x}  {} |{}|", offset, hex, ascii)
+}
+
+// "text" searches for the text, anything else is read as hex bytes
+// with optional spaces, like "7f 45 4c 46"
+fn parse_pattern(input: &str) -> HResult<Vec<u8>> {
+    let invalid = || HError::Log(format!("Invalid byte pattern: {}", input));
+
+    if input.starts_with('"') {
+        let text = input[1..].trim_end_matches('"');
+        if text.is_empty() { return Err(invalid()) }
+        return Ok(text.as_bytes().to_vec());
+    }
+
+    let digits = input.chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<Vec<char>>();
+    if digits.is_empty() || digits.len() % 2 != 0 { return Err(invalid()) }
+
+    digits.chunks(2)
+        .map(|pair| {
+            let pair = pair.iter().collect::<String>();
+            u8::from_str_radix(&pair, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+// Finds the first match starting at or after from, without reading
+// the whole file at once
+fn find_forward(path: &Path, pattern: &[u8], from: usize) -> HResult<Option<usize>> {
+    let mut file = std::fs::File::open(path)?;
+    file.seek(SeekFrom::Start(from as u64))?;
+
+    let mut buffer = vec![];
+    let mut chunk = vec![0; SEARCH_CHUNK];
+    let mut start = from;
+
+    loop {
+        let len = file.read(&mut chunk)?;
+        if len == 0 { return Ok(None) }
+        buffer.extend_from_slice(&chunk[..len]);
+
+        if let Some(pos) = buffer.windows(pattern.len()).position(|w| w == pattern) {
+            return Ok(Some(start + pos));
+        }
+
+        // A match could start in the last few bytes
+        let keep = (pattern.len() - 1).min(buffer.len());
+        let consumed = buffer.len() - keep;
+        buffer.drain(..consumed);
+        start += consumed;
+    }
+}
+
+// Finds the last match starting before before
+fn find_backward(path: &Path,
+                 pattern: &[u8],
+                 before: usize,
+                 size: usize) -> HResult<Option<usize>> {
+    let mut end = (before + pattern.len() - 1).min(size);
+
+    while end >= pattern.len() {
+        let start = end.saturating_sub(SEARCH_CHUNK + pattern.len() - 1);
+        let buffer = read_at(path, start, end - start)?;
+
+        if let Some(pos) = buffer.windows(pattern.len()).rposition(|w| w == pattern) {
+            return Ok(Some(start + pos));
+        }
+
+        if start == 0 { break }
+        end = start + pattern.len() - 1;
+    }
+
+    Ok(None)
+}
+
+
+// Full screen hex viewer, only the visible part of the file is read
+pub struct HexView {
+    core: WidgetCore,
+    path: PathBuf,
+    name: String,
+    size: usize,
+    // First line shown
+    line: usize,
+    pattern: Option<Vec<u8>>,
+    // Position and length of the current match
+    found: Option<(usize, usize)>
+}
+
+impl HexView {
+    pub fn new(core: &WidgetCore, file: &File) -> HResult<HexView> {
+        let size = file.path.metadata()?.len() as usize;
+
+        Ok(HexView {
+            core: core.clone(),
+            path: file.path.clone(),
+            name: file.name.clone(),
+            size: size,
+            line: 0,
+            pattern: None,
+            found: None
+        })
+    }
+
+    fn width(&self) -> usize {
+        bytes_per_line(self.core.coordinates.xsize() as usize)
+    }
+
+    fn height(&self) -> usize {
+        self.core.coordinates.ysize() as usize
+    }
+
+    fn last_line(&self) -> usize {
+        let lines = (self.size + self.width() - 1) / self.width();
+        lines.saturating_sub(self.height())
+    }
+
+    fn scroll(&mut self, amount: isize) {
+        let line = (self.line as isize + amount).max(0) as usize;
+        self.line = line.min(self.last_line());
+    }
+
+    // Centers pos if it's not visible already
+    fn show_pos(&mut self, pos: usize) {
+        let line = pos / self.width();
+        let height = self.height();
+
+        if line < self.line || line >= self.line + height {
+            self.line = line.saturating_sub(height / 2).min(self.last_line());
+        }
+    }
+
+    fn search(&mut self) -> HResult<()> {
+        // Cancelling would close the viewer otherwise
+        let input = match self.minibuffer("search bytes (hex or \"text)") {
+            Ok(input) => input,
+            Err(_) => return Ok(())
+        };
+
+        self.pattern = Some(parse_pattern(&input)?);
+        self.found = None;
+        self.search_next(true)
+    }
+
+    fn search_next(&mut self, forward: bool) -> HResult<()> {
+        let pattern = match self.pattern.clone() {
+            Some(pattern) => pattern,
+            None => return self.show_status("No search pattern")
+        };
+
+        let found = match (forward, self.found) {
+            (true, Some((pos, _))) => find_forward(&self.path, &pattern, pos + 1)?,
+            (true, None) => find_forward(&self.path, &pattern, self.line * self.width())?,
+            (false, Some((pos, _))) => find_backward(&self.path, &pattern, pos, self.size)?,
+            (false, None) => {
+                let pos = self.line * self.width();
+                find_backward(&self.path, &pattern, pos, self.size)?
+            }
+        };
+
+        match found {
+            Some(pos) => {
+                self.found = Some((pos, pattern.len()));
+                self.show_pos(pos);
+                Ok(())
+            }
+            None => self.show_status("Pattern not found")
+        }
+    }
+}
+
+impl Widget for HexView {
+    fn get_core(&self) -> HResult<&WidgetCore> {
+        Ok(&self.core)
+    }
+    fn get_core_mut(&mut self) -> HResult<&mut WidgetCore> {
+        Ok(&mut self.core)
+    }
+    fn refresh(&mut self) -> HResult<()> {
+        // The file might have shrunk, or the terminal was resized
+        self.line = self.line.min(self.last_line());
+        Ok(())
+    }
+
+    fn render_header(&self) -> HResult<String> {
+        Ok(format!("{} ({} bytes)", self.name, self.size))
+    }
+
+    fn render_footer(&self) -> HResult<String> {
+        let pos = self.line * self.width();
+        let percent = if self.size == 0 { 100 } else {
+            (pos + self.height() * self.width()).min(self.size) * 100 / self.size
+        };
+
+        Ok(format!("0x{:08x} / 0x{:08x}  {}%", pos, self.size, percent))
+    }
+
+    fn get_drawlist(&self) -> HResult<String> {
+        let (xpos, ypos) = self.get_coordinates()?.u16position();
+        let (width, height) = (self.width(), self.height());
+        let offset = self.line * width;
+        let bytes = read_at(&self.path, offset, width * height)?;
+
+        let output = self.get_clearlist()? +
+            &bytes.chunks(width)
+            .enumerate()
+            .map(|(i, chunk)| {
+                format!("{}{}{}",
+                        term::goto_xy(xpos, ypos + i as u16),
+                        term::reset(),
+                        hexdump_line(chunk, offset + i * width, width, self.found))
+            })
+            .collect::<String>();
+
+        Ok(output)
+    }
+
+    fn on_key(&mut self, key: Key) -> HResult<()> {
+        let page = self.height() as isize - 1;
+
+        match key {
+            Key::Char('q') | Key::Char('X') | Key::Esc => self.popup_finnished()?,
+            Key::Up | Key::Char('k') => self.scroll(-1),
+            Key::Down | Key::Char('j') => self.scroll(1),
+            Key::Char('K') => self.scroll(-10),
+            Key::Char('J') => self.scroll(10),
+            Key::PageUp | Key::Alt('v') => self.scroll(-page),
+            Key::PageDown | Key::Ctrl('v') => self.scroll(page),
+            Key::Home | Key::Char('<') => self.line = 0,
+            Key::End | Key::Char('>') => self.line = self.last_line(),
+            Key::Char('/') => self.search()?,
+            Key::Char('n') => self.search_next(true)?,
+            Key::Char('N') => self.search_next(false)?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_pattern, find_forward, find_backward, bytes_per_line,
+                hexdump_line, SEARCH_CHUNK};
+    use crate::term;
+    use std::path::PathBuf;
+
+    // Removed again when the test is done
+    struct TestFile(PathBuf);
+
+    impl TestFile {
+        fn new(name: &str, content: &[u8]) -> TestFile {
+            let path = std::env::temp_dir()
+                .join(format!("hunter-hexview-{}-{}", std::process::id(), name));
+            std::fs::write(&path, content).unwrap();
+            TestFile(path)
+        }
+    }
+
+    impl Drop for TestFile {
+        fn drop(&mut self) {
+            std::fs::remove_file(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn parse_hex_pattern() {
+        assert_eq!(parse_pattern("7f 45 4c 46").unwrap(), vec![0x7f, 0x45, 0x4c, 0x46]);
+        assert_eq!(parse_pattern("7f454C46").unwrap(), vec![0x7f, 0x45, 0x4c, 0x46]);
+        assert!(parse_pattern("7f4").is_err());
+        assert!(parse_pattern("7 f 4").is_err());
+        assert!(parse_pattern("zz").is_err());
+        assert!(parse_pattern("").is_err());
+    }
+
+    #[test]
+    fn parse_text_pattern() {
+        assert_eq!(parse_pattern("\"ELF").unwrap(), b"ELF".to_vec());
+        assert_eq!(parse_pattern("\"ELF\"").unwrap(), b"ELF".to_vec());
+        assert_eq!(parse_pattern("\"7f 45").unwrap(), b"7f 45".to_vec());
+        assert!(parse_pattern("\"").is_err());
+        assert!(parse_pattern("\"\"").is_err());
+    }
+
+    #[test]
+    fn find_across_chunks() {
+        let pos = SEARCH_CHUNK - 3;
+        let mut content = vec![0; SEARCH_CHUNK * 2];
+        content[pos..pos + 6].copy_from_slice(b"hunter");
+        let file = TestFile::new("chunks", &content);
+        let size = content.len();
+
+        assert_eq!(find_forward(&file.0, b"hunter", 0).unwrap(), Some(pos));
+        assert_eq!(find_forward(&file.0, b"hunter", pos).unwrap(), Some(pos));
+        assert_eq!(find_forward(&file.0, b"hunter", pos + 1).unwrap(), None);
+
+        assert_eq!(find_backward(&file.0, b"hunter", size, size).unwrap(), Some(pos));
+        assert_eq!(find_backward(&file.0, b"hunter", pos + 1, size).unwrap(), Some(pos));
+        assert_eq!(find_backward(&file.0, b"hunter", pos, size).unwrap(), None);
+    }
+
+    #[test]
+    fn find_backward_at_start() {
+        let file = TestFile::new("start", b"hunter hunter");
+
+        assert_eq!(find_backward(&file.0, b"hunter", 0, 13).unwrap(), None);
+        assert_eq!(find_backward(&file.0, b"hunter", 1, 13).unwrap(), Some(0));
+        assert_eq!(find_backward(&file.0, b"hunter", 7, 13).unwrap(), Some(0));
+        assert_eq!(find_backward(&file.0, b"hunter", 8, 13).unwrap(), Some(7));
+    }
+
+    #[test]
+    fn line_width() {
+        assert_eq!(bytes_per_line(200), 16);
+        assert_eq!(bytes_per_line(79), 16);
+        assert_eq!(bytes_per_line(78), 8);
+        assert_eq!(bytes_per_line(46), 8);
+        assert_eq!(bytes_per_line(45), 4);
+        assert_eq!(bytes_per_line(0), 4);
+    }
+
+    #[test]
+    fn dump_line() {
+        assert_eq!(hexdump_line(b"AB\x00", 16, 4, None),
+                   "00000010  41 42 00     |AB.|");
+        assert_eq!(hexdump_line(b"0123456789", 0, 16, None),
+                   "00000000  30 31 32 33 34 35 36 37  38 39                    |0123456789|");
+        assert_eq!(hexdump_line(b"AB", 16, 2, Some((17, 1))),
+                   format!("00000010  41 {}42{}  |A{}B{}|",
+                           term::invert(), term::reset(),
+                           term::invert(), term::reset()));
+    }
+}
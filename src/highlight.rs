@@ -0,0 +1,89 @@
+use syntect::easy::HighlightLines;
+use syntect::highlighting::ThemeSet;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::as_24_bit_terminal_escaped;
+use async_value::Stale;
+
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::files::File;
+use crate::fail::{HResult, HError};
+
+
+lazy_static! {
+    // Loading these takes a moment, so it only happens once the first
+    // text file is previewed
+    static ref SYNTAX_SET: SyntaxSet = SyntaxSet::load_defaults_newlines();
+    static ref THEME_SET: ThemeSet = ThemeSet::load_defaults();
+}
+
+pub const DEFAULT_THEME: &str = "base16-ocean.dark";
+
+// An unknown theme is only reported for the first preview
+static THEME_REPORTED: AtomicBool = AtomicBool::new(false);
+
+pub fn theme_names() -> Vec<&'static str> {
+    let mut names = THEME_SET.themes
+        .keys()
+        .map(|name| name.as_str())
+        .collect::<Vec<&str>>();
+    names.sort();
+    names
+}
+
+// Picks the syntax by extension, then by file name (Makefile, etc.),
+// then by the first line, so scripts without extension are found by
+// their shebang
+fn syntax_for(file: &File, first_line: &str) -> &'static SyntaxReference {
+    let ss: &'static SyntaxSet = &SYNTAX_SET;
+
+    file.path
+        .extension()
+        .and_then(|ext| ss.find_syntax_by_extension(&ext.to_string_lossy()))
+        .or_else(|| ss.find_syntax_by_extension(&file.name))
+        .or_else(|| ss.find_syntax_by_first_line(first_line))
+        .unwrap_or_else(|| ss.find_syntax_plain_text())
+}
+
+// Reads up to num lines and colors them with ANSI escapes. Highlighting
+// long lines can take a while, so this gives up as soon as the preview
+// isn't needed anymore.
+pub fn highlight_file(file: &File,
+                      num: usize,
+                      theme: &str,
+                      stale: &Stale) -> HResult<Vec<String>> {
+    let theme = match THEME_SET.themes.get(theme) {
+        Some(theme) => theme,
+        None if !THEME_REPORTED.swap(true, Ordering::Relaxed) => {
+            return HError::log(format!("Unknown syntax theme: {}, available: {}",
+                                       theme,
+                                       theme_names().join(", ")));
+        }
+        None => return HError::preview_failed(file)
+    };
+
+    let mut reader = std::io::BufReader::new(std::fs::File::open(&file.path)?);
+    let mut first_line = String::new();
+    reader.read_line(&mut first_line)?;
+
+    let syntax = syntax_for(file, &first_line);
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    let mut lines = Vec::with_capacity(num);
+    let mut line = first_line;
+
+    while lines.len() < num && !line.is_empty() {
+        if stale.is_stale()? { return HError::stale() }
+
+        let ranges = highlighter.highlight(&line, &SYNTAX_SET);
+        let highlighted = as_24_bit_terminal_escaped(&ranges, false)
+            .trim_end_matches(|c| c == '\r' || c == '\n')
+            .replace("\t", "    ");
+        lines.push(highlighted);
+
+        line.clear();
+        reader.read_line(&mut line)?;
+    }
+
+    Ok(lines)
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+
+use crate::files::File;
+use crate::proclist::{Cmd, ConcatOsString};
+use crate::fail::{HResult, HError, ErrorLog, put_log};
+
+
+// Points where a user command can be run, configured as hook_<name>
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    Cd,
+    Open,
+    Select,
+    TabSwitch,
+    ProcExit,
+    Quit
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::Cd => "cd",
+            Hook::Open => "open",
+            Hook::Select => "select",
+            Hook::TabSwitch => "tab",
+            Hook::ProcExit => "proc_exit",
+            Hook::Quit => "quit"
+        }
+    }
+}
+
+impl std::str::FromStr for Hook {
+    type Err = HError;
+
+    fn from_str(name: &str) -> HResult<Hook> {
+        match name {
+            "cd" => Ok(Hook::Cd),
+            "open" => Ok(Hook::Open),
+            "select" => Ok(Hook::Select),
+            "tab" => Ok(Hook::TabSwitch),
+            "proc_exit" => Ok(Hook::ProcExit),
+            "quit" => Ok(Hook::Quit),
+            _ => HError::log(format!("Unknown hook: {}", name))
+        }
+    }
+}
+
+// How long the cursor has to rest before the select hook runs
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    hooks: HashMap<Hook, String>,
+    // Latest debounced call and when it was made
+    pending: Arc<Mutex<Option<(Instant, Hook, File, Vec<File>)>>>
+}
+
+impl Hooks {
+    pub fn set(&mut self, hook: Hook, cmd: &str) {
+        self.hooks.insert(hook, cmd.to_string());
+    }
+
+    // For hooks like select that fire on every cursor move: only the
+    // last call runs, once no new one came in for DEBOUNCE_DELAY
+    pub fn run_debounced(&self,
+                         hook: Hook,
+                         cwd: &File,
+                         files: Vec<File>) -> HResult<()> {
+        if !self.hooks.contains_key(&hook) { return Ok(()) }
+
+        let mut pending = self.pending.lock()?;
+        let waiting = pending.is_some();
+        *pending = Some((Instant::now(), hook, cwd.clone(), files));
+
+        // The waiting thread picks up the new call
+        if waiting { return Ok(()) }
+
+        let hooks = self.clone();
+        std::thread::spawn(move || hooks.wait_for_debounced().log());
+
+        Ok(())
+    }
+
+    fn wait_for_debounced(&self) -> HResult<()> {
+        loop {
+            std::thread::sleep(DEBOUNCE_DELAY);
+
+            let mut pending = self.pending.lock()?;
+            let ready = match &*pending {
+                Some((time, ..)) => time.elapsed() >= DEBOUNCE_DELAY,
+                None => return Ok(())
+            };
+
+            if ready {
+                let (_, hook, cwd, files) = pending.take()?;
+                drop(pending);
+                return self.run(hook, &cwd, files, vec![]);
+            }
+        }
+    }
+
+    // Runs the command in the background with $s replaced by files,
+    // like the commands started with !. Output is thrown away, since
+    // the command runs while hunter owns the terminal.
+    pub fn run(&self,
+               hook: Hook,
+               cwd: &File,
+               files: Vec<File>,
+               env: Vec<(&str, String)>) -> HResult<()> {
+        let cmd = match self.hooks.get(&hook) {
+            Some(cmd) => cmd,
+            None => return Ok(())
+        };
+
+        let mut cmd = Cmd {
+            cmd: OsString::from(cmd.clone() + " "),
+            short_cmd: None,
+            args: None,
+            cwd: cwd.clone(),
+            cwd_files: Some(files),
+            tab_files: None,
+            tab_paths: None
+        };
+        let shell_cmd = cmd.process().concat();
+        let shell = std::env::var("SHELL").unwrap_or("sh".into());
+
+        let mut child = Command::new(shell)
+            .arg("-c")
+            .arg(shell_cmd)
+            .current_dir(&cwd.path)
+            .env("HUNTER_HOOK", hook.name())
+            .envs(env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        // Wait in the background so it doesn't turn into a zombie
+        std::thread::spawn(move || {
+            match child.wait() {
+                Ok(status) if !status.success() => {
+                    HError::log::<()>(format!("Hook {} failed: {}",
+                                              hook.name(),
+                                              status)).log();
+                }
+                Err(err) => { put_log(&HError::from(err)).ok(); }
+                _ => {}
+            }
+        });
+
+        Ok(())
+    }
+}
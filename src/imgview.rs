@@ -0,0 +1,306 @@
+use image::RgbImage;
+use async_value::Stale;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::files::File;
+use crate::term::ScreenExt;
+use crate::widget::{Widget, WidgetCore};
+use crate::fail::{HResult, HError, ErrorLog};
+
+
+// Ids for images sent to kitty, so they can be shown and deleted again
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+// How images are drawn, set with image_preview
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Graphics {
+    Kitty,
+    Sixel,
+    Unicode
+}
+
+impl Graphics {
+    // Half blocks work everywhere, including inside tmux, which doesn't
+    // pass the graphics escapes through
+    pub fn detect() -> Graphics {
+        if std::env::var_os("TMUX").is_some() { return Graphics::Unicode }
+        if crate::term::is_kitty() { return Graphics::Kitty }
+
+        let term = std::env::var("TERM").unwrap_or(String::new());
+        if term.starts_with("mlterm") ||
+            term.starts_with("yaft") ||
+            term.contains("sixel") {
+            Graphics::Sixel
+        } else {
+            Graphics::Unicode
+        }
+    }
+}
+
+impl std::str::FromStr for Graphics {
+    type Err = HError;
+
+    fn from_str(name: &str) -> HResult<Graphics> {
+        match name {
+            "auto" => Ok(Graphics::detect()),
+            "kitty" => Ok(Graphics::Kitty),
+            "sixel" => Ok(Graphics::Sixel),
+            "unicode" => Ok(Graphics::Unicode),
+            _ => HError::log(format!("Unknown image preview: {}", name))
+        }
+    }
+}
+
+// An image drawn with kitty's graphics protocol or sixel. Kitty gets
+// the pixels once and afterwards only needs to be told where to show
+// them, sixel has to be sent again every time it's drawn.
+pub struct ImageView {
+    core: WidgetCore,
+    graphics: Graphics,
+    id: usize,
+    data: String,
+    // Size in cells, it's not drawn if it doesn't fit anymore
+    cells: (usize, usize),
+    transmitted: AtomicBool
+}
+
+impl PartialEq for ImageView {
+    fn eq(&self, other: &ImageView) -> bool {
+        self.id == other.id
+    }
+}
+
+impl ImageView {
+    pub fn new(core: &WidgetCore,
+               file: &File,
+               graphics: Graphics,
+               stale: &Stale) -> HResult<ImageView> {
+        let (cell_width, cell_height) = crate::term::cell_size()?;
+        let (xsize, ysize) = core.coordinates.size_u();
+        // Sixels are drawn in bands of 6 pixels, the last one could
+        // reach into the next line otherwise
+        let height = (ysize * cell_height).saturating_sub(6);
+
+        let image = load(file, xsize * cell_width, height, stale)?;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let pixels = image.into_raw();
+
+        let data = match graphics {
+            Graphics::Kitty => kitty(id, width, height, &pixels),
+            _ => sixel(width, height, &pixels)
+        };
+
+        if stale.is_stale()? { return HError::stale() }
+
+        Ok(ImageView {
+            core: core.clone(),
+            graphics: graphics,
+            id: id,
+            data: data,
+            cells: ((width + cell_width - 1) / cell_width,
+                    (height + cell_height - 1) / cell_height),
+            transmitted: AtomicBool::new(false)
+        })
+    }
+}
+
+impl Widget for ImageView {
+    fn get_core(&self) -> HResult<&WidgetCore> {
+        Ok(&self.core)
+    }
+    fn get_core_mut(&mut self) -> HResult<&mut WidgetCore> {
+        Ok(&mut self.core)
+    }
+    fn refresh(&mut self) -> HResult<()> {
+        Ok(())
+    }
+    fn get_drawlist(&self) -> HResult<String> {
+        let (xsize, ysize) = self.get_coordinates()?.size_u();
+        let (xpos, ypos) = self.get_coordinates()?.u16position();
+        let mut output = self.get_clearlist()?;
+
+        if self.cells.0 > xsize || self.cells.1 > ysize {
+            return Ok(output);
+        }
+
+        output += &crate::term::goto_xy(xpos, ypos);
+
+        match self.graphics {
+            Graphics::Kitty => {
+                if !self.transmitted.swap(true, Ordering::Relaxed) {
+                    output += &self.data;
+                }
+                output += &format!("\x1b_Ga=d,d=i,i={},q=2\x1b\\", self.id);
+                output += &format!("\x1b_Ga=p,i={},q=2\x1b\\", self.id);
+            }
+            _ => output += &self.data
+        }
+
+        Ok(output)
+    }
+
+    // Kitty draws images above the text, so clearing the text isn't
+    // enough. Only the placement goes, the next draw shows it again.
+    fn clear(&self) -> HResult<()> {
+        let mut clearlist = self.get_clearlist()?;
+        if self.graphics == Graphics::Kitty {
+            clearlist += &format!("\x1b_Ga=d,d=i,i={},q=2\x1b\\", self.id);
+        }
+        self.write_to_screen(&clearlist)
+    }
+}
+
+impl Drop for ImageView {
+    fn drop(&mut self) {
+        // Frees the image in kitty, sixels are just overwritten
+        if self.graphics == Graphics::Kitty {
+            let delete = format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", self.id);
+            self.core.screen.clone().write_str(&delete).log();
+        }
+    }
+}
+
+// Lines of upper half blocks with two pixels each, the top one in the
+// foreground color, the bottom one in the background
+pub fn half_blocks(file: &File,
+                   xsize: usize,
+                   ysize: usize,
+                   stale: &Stale) -> HResult<Vec<String>> {
+    let image = load(file, xsize, ysize * 2, stale)?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    let pixels = image.into_raw();
+    let pixel = |x: usize, y: usize| {
+        let pos = (y * width + x) * 3;
+        (pixels[pos], pixels[pos + 1], pixels[pos + 2])
+    };
+
+    let lines = (0..height).step_by(2).map(|y| {
+        (0..width).map(|x| {
+            let (r, g, b) = pixel(x, y);
+            if y + 1 < height {
+                let (br, bg, bb) = pixel(x, y + 1);
+                format!("\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀",
+                        r, g, b, br, bg, bb)
+            } else {
+                format!("\x1b[38;2;{};{};{}m\x1b[49m▀", r, g, b)
+            }
+        }).collect::<String>()
+    }).collect();
+
+    if stale.is_stale()? { return HError::stale() }
+
+    Ok(lines)
+}
+
+// Decoding can't be interrupted, but scaling and drawing are skipped
+// once the preview isn't needed anymore
+fn load(file: &File, width: usize, height: usize, stale: &Stale) -> HResult<RgbImage> {
+    let image = image::open(&file.path)?;
+    if stale.is_stale()? { return HError::stale() }
+
+    let image = image.thumbnail(width.max(1) as u32, height.max(1) as u32).to_rgb();
+    if stale.is_stale()? { return HError::stale() }
+
+    Ok(image)
+}
+
+// Sends the raw RGB data in chunks of at most 4096 bytes, as the
+// protocol demands. q=2 keeps kitty from answering on stdin.
+fn kitty(id: usize, width: usize, height: usize, pixels: &[u8]) -> String {
+    let data = base64::encode(pixels);
+    let chunks = data.as_bytes().chunks(4096).collect::<Vec<&[u8]>>();
+    let mut output = String::with_capacity(data.len() + chunks.len() * 16);
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        let chunk = std::str::from_utf8(chunk).unwrap_or("");
+
+        if i == 0 {
+            output += &format!("\x1b_Ga=t,f=24,s={},v={},i={},q=2,m={};{}\x1b\\",
+                               width, height, id, more, chunk);
+        } else {
+            output += &format!("\x1b_Gm={};{}\x1b\\", more, chunk);
+        }
+    }
+
+    output
+}
+
+// Uses a 6x6x6 color cube as palette, which is good enough for a preview
+// and doesn't need a quantization pass
+fn sixel(width: usize, height: usize, pixels: &[u8]) -> String {
+    let mut output = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+
+    for color in 0..216 {
+        output += &format!("#{};2;{};{};{}",
+                           color,
+                           color / 36 * 20,
+                           color / 6 % 6 * 20,
+                           color % 6 * 20);
+    }
+
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    let colors = pixels.chunks(3)
+        .map(|p| level(p[0]) * 36 + level(p[1]) * 6 + level(p[2]))
+        .collect::<Vec<usize>>();
+
+    for band in (0..height).step_by(6) {
+        let rows = std::cmp::min(6, height - band);
+        let mut used = [false; 216];
+        for y in band..band + rows {
+            for x in 0..width {
+                used[colors[y * width + x]] = true;
+            }
+        }
+
+        for color in (0..216).filter(|color| used[*color]) {
+            output += &format!("#{}", color);
+
+            let sixels = (0..width).map(|x| {
+                let bits = (0..rows)
+                    .filter(|row| colors[(band + row) * width + x] == color)
+                    .fold(0, |bits, row| bits | 1 << row);
+                (63 + bits) as u8 as char
+            });
+            push_runs(&mut output, sixels);
+            output.push('$');
+        }
+
+        if band + 6 < height {
+            output.push('-');
+        }
+    }
+
+    output += "\x1b\\";
+    output
+}
+
+// Repeated sixels are written as !<count><sixel>
+fn push_runs(output: &mut String, sixels: impl Iterator<Item=char>) {
+    let mut run: Option<(char, usize)> = None;
+
+    for sixel in sixels {
+        run = match run {
+            Some((last, count)) if last == sixel => Some((last, count + 1)),
+            Some((last, count)) => {
+                push_run(output, last, count);
+                Some((sixel, 1))
+            }
+            None => Some((sixel, 1))
+        };
+    }
+
+    if let Some((last, count)) = run {
+        push_run(output, last, count);
+    }
+}
+
+fn push_run(output: &mut String, sixel: char, count: usize) {
+    if count > 3 {
+        output.push_str(&format!("!{}{}", count, sixel));
+    } else {
+        for _ in 0..count { output.push(sixel) }
+    }
+}
@@ -3,7 +3,8 @@ use unicode_width::UnicodeWidthStr;
 
 use std::path::{Path, PathBuf};
 
-use crate::files::{File, Files};
+use crate::files::{File, Files, Kind, SortKey};
+use crate::filter::Filter;
 use crate::fail::{HResult, HError, ErrorLog};
 use crate::term;
 use crate::widget::{Widget, WidgetCore};
@@ -29,12 +30,23 @@ impl Listable for ListView<Files> {
     }
 
     fn on_new(&mut self) -> HResult<()> {
-        let show_hidden = self.config().show_hidden();
-        self.content.show_hidden = show_hidden;
+        let config = self.config();
+        self.content.show_hidden = config.show_hidden();
+        self.content.match_mode = config.match_mode;
+        self.content.smart_case = config.smart_case;
+
+        if self.content.sort_chain.is_none() {
+            self.content.sort_chain = Some(config.sort_chain.clone());
+            self.content.sort();
+        }
         Ok(())
     }
 
     fn on_refresh(&mut self) -> HResult<()> {
+        if self.content.take_flat_pending()? > 0 {
+            self.core.set_dirty();
+        }
+
         if self.content.len() == 0 {
             let path = &self.content.directory.path;
             let placeholder = File::new_placeholder(&path)?;
@@ -79,6 +91,9 @@ impl Listable for ListView<Files> {
             Key::Alt('s') => { self.search_next().log(); }
             Key::Alt('S') => { self.search_prev().log(); }
             Key::Ctrl('f') => { self.filter().log(); }
+            Key::Alt('F') => { self.pop_filter().log(); }
+            Key::Alt('q') => { self.query_filter().log(); }
+            Key::Alt('Q') => { self.query_select().log(); }
             Key::Left => self.goto_grand_parent()?,
             Key::Right => self.goto_selected()?,
             Key::Char(' ') => self.multi_select_file(),
@@ -87,6 +102,8 @@ impl Listable for ListView<Files> {
             Key::Char('H') => self.toggle_hidden(),
             Key::Char('r') => self.reverse_sort(),
             Key::Char('s') => self.cycle_sort(),
+            Key::Char('o') => { self.pick_sort().log(); }
+            Key::Char('C') => self.toggle_case_sensitive(),
             Key::Char('N') => self.select_next_mtime(),
             Key::Char('n') => self.select_prev_mtime(),
             Key::Char('d') => self.toggle_dirs_first(),
@@ -269,6 +286,41 @@ impl ListView<Files>
         self.show_status(&format!("Sorting by: {}", self.content.sort)).log();
     }
 
+    // Takes a sort chain like "ext,-mtime". The first key becomes the
+    // main sort mode, the rest are used when files are equal on it
+    fn pick_sort(&mut self) -> HResult<()> {
+        let input = self.minibuffer("sort by (e.g. ext,-mtime)")?;
+        let mut chain = SortKey::parse_chain(&input)?;
+        if chain.is_empty() { return HError::minibuffer_empty() }
+        let first = chain.remove(0);
+
+        let file = self.clone_selected_file();
+        self.content.set_sort(first.by);
+        self.content.sort_reverse = first.reverse;
+        self.content.sort_chain = Some(chain);
+        self.content.sort();
+        self.select_file(&file);
+        self.refresh().log();
+
+        let chain = self.content.full_sort_chain()
+            .iter()
+            .map(|key| key.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        self.show_status(&format!("Sorting by: {}", chain)).log();
+        Ok(())
+    }
+
+    fn toggle_case_sensitive(&mut self) {
+        let file = self.clone_selected_file();
+        self.content.toggle_case_sensitive();
+        self.content.sort();
+        self.select_file(&file);
+        self.refresh().log();
+        self.show_status(&format!("Case insensitive sorting: {}",
+                                  self.content.case_insensitive)).log();
+    }
+
     fn reverse_sort(&mut self) {
         let file = self.clone_selected_file();
         self.content.reverse_sort();
@@ -354,7 +406,7 @@ impl ListView<Files>
         self.selected_file_mut().toggle_selection();
 
         let selection = self.get_selection();
-        let line = self.render_line(self.selected_file());
+        let line = self.render_selected_line();
         self.buffer[selection] = line;
 
         self.move_down();
@@ -372,7 +424,7 @@ impl ListView<Files>
         self.selected_file_mut().toggle_tag()?;
 
         let selection = self.get_selection();
-        let line = self.render_line(self.selected_file());
+        let line = self.render_selected_line();
         self.buffer[selection] = line;
 
         self.move_down();
@@ -424,11 +476,7 @@ impl ListView<Files>
             .iter()
             .skip(selection+1)
             .find(|file| {
-                if file.name.to_lowercase().contains(&prev_search) {
-                    true
-                } else {
-                    false
-                }
+                self.content.match_mode.matches(&prev_search, &file.name)
             }).clone();
 
         if let Some(file) = file {
@@ -456,11 +504,7 @@ impl ListView<Files>
             .iter()
             .skip(selection+1)
             .find(|file| {
-                if file.name.to_lowercase().contains(&prev_search) {
-                    true
-                } else {
-                    false
-                }
+                self.content.match_mode.matches(&prev_search, &file.name)
             }).cloned();
 
         self.reverse_sort();
@@ -476,46 +520,169 @@ impl ListView<Files>
         Ok(())
     }
 
+    // Adds a new filter on top of the existing ones, updating the
+    // listing while it's being typed
     fn filter(&mut self) -> HResult<()> {
         let selected_file = self.selected_file().clone();
+        let filters = self.content.get_filters();
 
         loop {
-            let filter = self.minibuffer_continuous("filter");
+            let input = self.minibuffer_continuous("filter");
 
-            match filter {
+            match input {
                 Err(HError::MiniBufferInputUpdated(input)) => {
-                    self.content.set_filter(Some(input));
-                    self.refresh().ok();
+                    // Incomplete regexes are expected while typing
+                    if let Ok(filter) = Filter::parse(&input) {
+                        let mut new_filters = filters.clone();
+                        new_filters.push(filter);
+                        self.content.set_filters(new_filters);
+                        self.refresh().ok();
 
-                    self.select_file(&selected_file);
-                    self.draw().ok();
+                        self.select_file(&selected_file);
+                        self.draw().ok();
+                    }
 
                     continue;
                 }
-                Err(HError::MiniBufferEmptyInput) |
-                Err(HError::MiniBufferCancelledInput) => {
-                    self.content.set_filter(None);
-                    self.refresh().ok();
-                    self.select_file(&selected_file);
+                Ok(input) => {
+                    match Filter::parse(&input) {
+                        Ok(filter) => {
+                            let mut new_filters = filters.clone();
+                            new_filters.push(filter);
+                            self.content.set_filters(new_filters);
+                            self.show_status(&format!("Filtering with: \"{}\"",
+                                                      input)).log();
+                        }
+                        Err(err) => {
+                            self.content.set_filters(filters.clone());
+                            self.show_status(&format!("{}", err)).log();
+                        }
+                    }
+                }
+                // Confirming an empty filter removes all of them
+                Err(HError::MiniBufferEmptyInput) => {
+                    self.content.set_filters(vec![]);
+                    self.show_status("Removed all filters").log();
+                }
+                Err(_) => {
+                    self.content.set_filters(filters.clone());
                 }
-                _ => {}
             }
 
-            let msgstr = filter.clone().unwrap_or(String::from(""));
-            self.show_status(&format!("Filtering with: \"{}\"", msgstr)).log();
-
+            self.refresh().ok();
+            self.select_file(&selected_file);
             break;
         }
 
         Ok(())
     }
 
-    fn render_line(&self, file: &File) -> String {
+    fn pop_filter(&mut self) -> HResult<()> {
+        let selected_file = self.selected_file().clone();
+        let filter = self.content.pop_filter()?;
+
+        self.refresh().log();
+        self.select_file(&selected_file);
+        self.show_status(&format!("Removed filter: \"{}\"", filter)).log();
+        Ok(())
+    }
+
+    // Only shows files matching a query like "size>10M and mtime<2d"
+    fn query_filter(&mut self) -> HResult<()> {
+        let selected_file = self.selected_file().clone();
+        let input = self.minibuffer("query")?;
+        let filter = Filter::query(&input)?;
+
+        self.content.push_filter(filter);
+        self.refresh().log();
+        self.select_file(&selected_file);
+        self.show_status(&format!("Filtering with: \"{}\"", input)).log();
+        Ok(())
+    }
+
+    fn query_select(&mut self) -> HResult<()> {
+        let input = self.minibuffer("select query")?;
+        let filter = Filter::query(&input)?;
+        let match_mode = self.content.match_mode;
+        let smart_case = self.content.smart_case;
+
+        let mut selected = 0;
+        for file in self.content.get_files_mut() {
+            if file.kind != Kind::Placeholder &&
+                filter.matches(file, match_mode, smart_case) {
+                file.selected = true;
+                selected += 1;
+            }
+        }
+
+        self.content.set_dirty();
+        self.refresh().log();
+        self.show_status(&format!("Selected {} files", selected)).log();
+        Ok(())
+    }
+
+    fn match_positions(&self, name: &str) -> Vec<usize> {
+        let match_mode = self.content.match_mode;
+        let smart_case = self.content.smart_case;
+
+        match self.searching.as_ref() {
+            Some(pattern) => match_mode.positions(pattern, name),
+            None => self.content.filters
+                .iter()
+                .flat_map(|filter| filter.positions(name, match_mode, smart_case))
+                .collect()
+        }
+    }
+
+    // Colors the chars of the file name matching the filter/search. line
+    // is cut off to fit, so matches are looked for in the part that's
+    // still visible first, the full name is only used if that fails.
+    fn highlight_matches(&self,
+                         line: &str,
+                         name: &str,
+                         name_offset: usize,
+                         file_color: &str) -> String {
+        let visible_len = line.chars().count().saturating_sub(name_offset);
+        let visible_name = name.chars().take(visible_len).collect::<String>();
+
+        let mut positions = self.match_positions(&visible_name);
+        if positions.is_empty() && visible_name.len() < name.len() {
+            positions = self.match_positions(name);
+        }
+        positions.retain(|pos| *pos < visible_len);
+        if positions.is_empty() { return line.to_string() }
+
+        line.chars()
+            .enumerate()
+            .map(|(i, ch)| {
+                let in_name = i >= name_offset;
+                if in_name && positions.contains(&(i - name_offset)) {
+                    format!("{}{}{}", term::color_red(), ch, file_color)
+                } else {
+                    ch.to_string()
+                }
+            })
+            .collect()
+    }
+
+    fn render_selected_line(&self) -> String {
+        let selection = self.get_selection();
+        let guide = self.content
+            .get_tree_guides()
+            .get(selection)
+            .cloned()
+            .unwrap_or_default();
+
+        self.render_line(self.selected_file(), &guide)
+    }
+
+    fn render_line(&self, file: &File, guide: &str) -> String {
         let icon = if self.config().icons {
             file.icon()
         } else { "" };
 
-        let name = String::from(icon) + &file.name;
+        let name = String::from(guide) + icon + &file.name;
+        let name_offset = name.chars().count() - file.name.chars().count();
         let (size, unit) = file.calculate_size().unwrap_or((0, "".to_string()));
 
 
@@ -530,6 +697,7 @@ impl ListView<Files>
         let (name, selection_color) =  if file.is_selected() {
             (selection_gap + &name, crate::term::color_yellow())
         } else { (name.clone(), "".to_string()) };
+        let name_offset = if file.is_selected() { name_offset + 2 } else { name_offset };
 
         let (link_indicator, link_indicator_len) = if file.target.is_some() {
             (format!("{}{}{}",
@@ -548,6 +716,20 @@ impl ListView<Files>
         let padding = xsize - padding as u16;
         let padding = padding - tag_len;
 
+        let file_color = match &file.color {
+            Some(color) => term::from_lscolor(color),
+            None => term::normal_color()
+        } + &selection_color;
+        let highlighted = self.highlight_matches(&sized_string,
+                                                 &file.name,
+                                                 name_offset,
+                                                 &file_color);
+        // Escape codes take up chars, but no space on screen
+        let padding = padding as usize
+            + highlighted.chars().count()
+            - sized_string.chars().count();
+        let sized_string = highlighted;
+
         format!(
             "{}{}{}{}{}{}{}{}",
             termion::cursor::Save,
@@ -558,14 +740,14 @@ impl ListView<Files>
                                        selection_color,
                                        &sized_string,
                                        term::normal_color(),
-                                       padding = padding as usize),
+                                       padding = padding),
                 _ => format!("{}{}{}{:padding$}{}",
                              tag,
                              term::normal_color(),
                              selection_color,
                              &sized_string,
                              term::normal_color(),
-                             padding = padding as usize),
+                             padding = padding),
             } ,
             termion::cursor::Restore,
             termion::cursor::Right(size_pos),
@@ -577,10 +759,16 @@ impl ListView<Files>
     }
 
     fn render(&self) -> Vec<String> {
+        let guides = self.content.get_tree_guides();
+
         self.content
             .get_files()
             .iter()
-            .map(|file| self.render_line(&file))
+            .enumerate()
+            .map(|(i, file)| {
+                let guide = guides.get(i).map(|g| g.as_str()).unwrap_or("");
+                self.render_line(&file, guide)
+            })
             .collect()
     }
 }
@@ -24,6 +24,13 @@ extern crate systemstat;
 extern crate osstrtools;
 extern crate pathbuftools;
 extern crate async_value;
+extern crate regex;
+extern crate rlua;
+#[macro_use]
+extern crate serde_json;
+extern crate syntect;
+extern crate image;
+extern crate base64;
 
 use failure::Fail;
 
@@ -39,6 +46,7 @@ mod term;
 mod textview;
 mod widget;
 mod hbox;
+mod split;
 mod tabview;
 mod fail;
 mod minibuffer;
@@ -51,6 +59,22 @@ mod fscache;
 mod config;
 mod stats;
 mod icon;
+mod finder;
+mod grep;
+mod fuzzy;
+mod filter;
+mod query;
+mod session;
+mod args;
+mod shell;
+mod remote;
+mod hooks;
+mod script;
+mod plugin;
+mod opener;
+mod highlight;
+mod imgview;
+mod hexview;
 
 
 
@@ -59,9 +83,15 @@ mod icon;
 
 use widget::{Widget, WidgetCore};
 use term::ScreenExt;
-use fail::{HResult, HError};
+use fail::{HResult, HError, ErrorLog};
 use file_browser::FileBrowser;
-use tabview::TabView;
+use tabview::{TabView, Tabbable};
+use split::SplitView;
+use session::Session;
+use config::Config;
+use fscache::FsCache;
+use files::File;
+use args::ARGS;
 
 
 fn reset_screen(core: &mut WidgetCore) -> HResult<()> {
@@ -80,8 +110,23 @@ fn die_gracefully(core: &WidgetCore) {
 }
 
 fn main() -> HResult<()> {
+    // Handles --help/--version and bad arguments before the screen is set up
+    lazy_static::initialize(&ARGS);
+
+    if let Some(command) = &ARGS.remote {
+        match remote::run_client(ARGS.socket.as_ref().map(|p| p.as_path()), command) {
+            Ok(true) => return Ok(()),
+            Ok(false) => std::process::exit(1),
+            Err(err) => {
+                eprintln!("hunter: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
     // do this early so it might be ready when needed
     crate::files::load_tags().ok();
+    lazy_static::initialize(&plugin::PLUGINS);
 
     let mut core = WidgetCore::new().expect("Can't create WidgetCore!");
 
@@ -98,14 +143,76 @@ fn main() -> HResult<()> {
     }
 }
 
+// Starts in path, or in its parent with path selected if it's a file
+fn start_browser(core: &WidgetCore, path: &std::path::Path) -> HResult<FileBrowser> {
+    // Symlinks are kept, so the browser shows the path as given
+    let path = std::env::current_dir()?
+        .join(path)
+        .components()
+        .filter(|part| *part != std::path::Component::CurDir)
+        .collect::<std::path::PathBuf>();
+
+    if !path.exists() {
+        HError::log::<()>(format!("Can't open {}: No such file or directory",
+                                  path.to_string_lossy())).log();
+        return FileBrowser::new(core, None, None);
+    }
+
+    if path.is_dir() {
+        return FileBrowser::new_in(core, None, None, path);
+    }
+
+    let dir = path.parent()?.to_path_buf();
+    let fs_cache = FsCache::new(core.get_sender());
+    let selection = File::new_from_path(&path, None)?;
+    fs_cache.set_selections(&File::new_from_path(&dir, None)?,
+                            Some(selection),
+                            vec![])?;
+
+    FileBrowser::new_in(core, Some(fs_cache), None, dir)
+}
+
 fn run(mut core: WidgetCore) -> HResult<()> {
     core.screen.clear()?;
 
-    let filebrowser = FileBrowser::new(&core, None)?;
-    let mut tabview = TabView::new(&core);
-    tabview.push_widget(filebrowser)?;
+    let session_name = ARGS.session.clone();
+    let use_session = session_name.is_some() ||
+        Config::load().map(|config| config.restore_session).unwrap_or(false);
+    // Starting somewhere specific makes restoring the session pointless
+    let restore_session = session_name.is_some() ||
+        (use_session && ARGS.path.is_none());
+    let session_name = session_name.unwrap_or("default".to_string());
+
+    let session_tabs = if restore_session {
+        Session::load(&session_name)
+            .and_then(|session| session.restore(&core))
+            .log_and()
+            .ok()
+    } else { None };
+
+    let mut tabview = match session_tabs {
+        Some(tabview) => tabview,
+        None => {
+            let filebrowser = match &ARGS.path {
+                Some(path) => start_browser(&core, path)?,
+                None => FileBrowser::new(&core, None, None)?
+            };
+            let splitview = SplitView::new(&core, filebrowser);
+            let mut tabview = TabView::new(&core);
+            tabview.push_widget(splitview)?;
+            tabview
+        }
+    };
+
+    let socket = remote::start_server(core.get_sender()).log_and().ok();
 
-    tabview.handle_input()?;
+    let result = tabview.handle_input();
+    tabview.on_quit().log();
+    if use_session {
+        Session::from_tabs(&tabview).save(&session_name).log();
+    }
+    socket.map(|socket| std::fs::remove_file(socket).ok());
+    result?;
 
     // core.screen.cursor_show()?;
     // core.screen.flush()?;
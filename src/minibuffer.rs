@@ -6,6 +6,7 @@ use crate::coordinates::{Coordinates};
 use crate::widget::{Widget, WidgetCore};
 use crate::fail::{HResult, HError, ErrorLog};
 use crate::term::ScreenExt;
+use crate::fuzzy::MatchMode;
 
 type HMap = HashMap<String, Vec<String>>;
 
@@ -136,7 +137,8 @@ pub struct MiniBuffer {
     history: History,
     completions: Vec<String>,
     last_completion: Option<String>,
-    continuous: bool
+    continuous: bool,
+    selecting: bool
 }
 
 impl MiniBuffer {
@@ -154,11 +156,22 @@ impl MiniBuffer {
             history: History::new(),
             completions: vec![],
             last_completion: None,
-            continuous: false
+            continuous: false,
+            selecting: false
         }
     }
 
     pub fn query(&mut self, query: &str, cont: bool) -> HResult<String> {
+        self.selecting = false;
+        self.run_query(query, cont)
+    }
+
+    pub fn query_selection(&mut self, query: &str) -> HResult<String> {
+        self.selecting = true;
+        self.run_query(query, true)
+    }
+
+    fn run_query(&mut self, query: &str, cont: bool) -> HResult<String> {
         self.continuous = cont;
 
         if !cont || self.query != query {
@@ -171,7 +184,9 @@ impl MiniBuffer {
 
         match self.popup() {
             Err(HError::MiniBufferCancelledInput) => self.input_cancelled()?,
-            err @ Err(HError::MiniBufferInputUpdated(_)) => err?,
+            // Also sent from outside to get the results refreshed
+            Err(HError::MiniBufferInputUpdated(_)) => self.input_updated()?,
+            err @ Err(HError::MiniBufferSelectionMoved(_)) => err?,
             _ => {}
         };
 
@@ -202,7 +217,8 @@ impl MiniBuffer {
                 .take(1)
                 .map(|s| s.to_string())
                 .collect::<String>();
-            let completions = find_files(part.clone());
+            let match_mode = self.config().match_mode;
+            let completions = find_files(part.clone(), match_mode);
 
             if let Ok(mut completions) = completions {
                 let completion = completions.pop()?;
@@ -210,12 +226,13 @@ impl MiniBuffer {
                 self.input
                     = self.input[..self.input.len() - part.len()].to_string();
                 self.input.push_str(&completion);
-                self.position += &completion.len() - part.len();
+                self.position = (self.position + completion.len())
+                    .saturating_sub(part.len());
 
                 self.last_completion = Some(completion);
                 self.completions = completions;
             } else {
-                let completions = find_bins(&part);
+                let completions = find_bins(&part, match_mode);
 
                 if let Ok(mut completions) = completions {
                     let completion = completions.pop()?;
@@ -223,7 +240,8 @@ impl MiniBuffer {
                     self.input = self.input[..self.input.len()
                                             - part.len()].to_string();
                     self.input.push_str(&completion);
-                    self.position += &completion.len() - part.len();
+                    self.position = (self.position + completion.len())
+                        .saturating_sub(part.len());
 
                     self.last_completion = Some(completion);
                     self.completions = completions;
@@ -349,19 +367,33 @@ impl MiniBuffer {
     }
 }
 
-pub fn find_bins(comp_name: &str) -> HResult<Vec<String>> {
+// Completions are sorted with the best match last, since they get popped
+fn sort_completions(completions: &mut Vec<String>,
+                    pattern: &str,
+                    match_mode: MatchMode) {
+    completions.sort_by_key(|completion| {
+        let name = completion.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+        match_mode.score(pattern, name).unwrap_or(0)
+    });
+}
+
+pub fn find_bins(comp_name: &str, match_mode: MatchMode) -> HResult<Vec<String>> {
     let paths = std::env::var_os("PATH")?
         .to_string_lossy()
         .split(":")
         .map(|s| s.to_string())
         .collect::<Vec<String>>();
 
-    let completions = paths.iter().map(|path| {
+    let mut completions = paths.iter().map(|path| {
         if let Ok(read_dir) = std::fs::read_dir(path) {
             read_dir.map(|file| {
                 let file = file.unwrap();
                 let name = file.file_name().into_string().unwrap();
-                if name.starts_with(comp_name) {
+                let matches = match match_mode {
+                    MatchMode::Substring => name.starts_with(comp_name),
+                    MatchMode::Fuzzy => match_mode.matches(comp_name, &name)
+                };
+                if matches {
                     Ok(name)
                 } else {
                     Err(HError::NoCompletionsError)
@@ -373,10 +405,11 @@ pub fn find_bins(comp_name: &str) -> HResult<Vec<String>> {
         .map(|result| result.unwrap())
         .collect::<Vec<String>>();
     if completions.is_empty() { return Err(HError::NoCompletionsError); }
+    sort_completions(&mut completions, comp_name, match_mode);
     Ok(completions)
 }
 
-pub fn find_files(comp_name: String) -> HResult<Vec<String>> {
+pub fn find_files(comp_name: String, match_mode: MatchMode) -> HResult<Vec<String>> {
     let mut path = std::env::current_dir().unwrap();
     let comp_path = std::path::PathBuf::from(&comp_name);
     path.push(&comp_path);
@@ -390,10 +423,14 @@ pub fn find_files(comp_name: String) -> HResult<Vec<String>> {
 
     let reader = std::fs::read_dir(&dir)?;
 
-    let completions = reader.map(|file| {
+    let mut completions = reader.map(|file| {
         let file = file?;
         let name = file.file_name().into_string().unwrap();
-        if name.starts_with(&filename_part) {
+        let matches = match match_mode {
+            MatchMode::Substring => name.starts_with(&filename_part),
+            MatchMode::Fuzzy => match_mode.matches(&filename_part, &name)
+        };
+        if matches {
             if file.file_type().unwrap().is_dir() {
                 Ok(format!("{}{}/", prefix, name))
             } else {
@@ -406,6 +443,7 @@ pub fn find_files(comp_name: String) -> HResult<Vec<String>> {
       .map(|res| res.unwrap() )
       .collect::<Vec<String>>();
     if completions.is_empty() { return Err(HError::NoCompletionsError); }
+    sort_completions(&mut completions, &filename_part, match_mode);
     Ok(completions)
 }
 
@@ -473,6 +511,12 @@ impl Widget for MiniBuffer {
                     self.position += 1;
                 }
             }
+            Key::Up | Key::Ctrl('p') if self.selecting => {
+                HError::selection_moved(-1)?
+            }
+            Key::Down | Key::Ctrl('n') if self.selecting => {
+                HError::selection_moved(1)?
+            }
             Key::Up | Key::Ctrl('p') | Key::Alt('p') => {
                 self.history_up()?;
             }
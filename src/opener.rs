@@ -0,0 +1,588 @@
+use termion::event::Key;
+
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::files::File;
+use crate::filter::glob_match;
+use crate::widget::{Widget, WidgetCore};
+use crate::coordinates::Coordinates;
+use crate::term;
+use crate::fail::{HResult, HError, ErrorLog};
+
+
+// Rules deciding how files are opened, read from the opener file. Each
+// line looks like
+//
+//   mime:image/*   bg   : feh -- "$@"
+//
+// The match is mime:GLOB, ext:EXT,EXT.. or glob:GLOB on the file
+// name, followed by an optional mode. The command is run with sh -c
+// and gets the files as arguments. The first matching rule wins.
+#[derive(Debug, Clone)]
+pub struct Opener {
+    rules: Vec<Rule>,
+    // Of the opener file when it was read, to notice changes
+    modified: Option<SystemTime>
+}
+
+#[derive(Debug, Clone)]
+pub struct Rule {
+    matcher: Matcher,
+    pub mode: OpenMode,
+    pub cmd: String
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Mime(Vec<char>),
+    Ext(Vec<String>),
+    Glob(Vec<char>),
+    Any
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OpenMode {
+    // Takes over the terminal until the command exits
+    Foreground,
+    // Runs in the process list, see ProcView
+    Background,
+    // Runs in a new terminal window
+    Terminal
+}
+
+impl std::str::FromStr for OpenMode {
+    type Err = HError;
+
+    fn from_str(mode: &str) -> HResult<OpenMode> {
+        match mode {
+            "fg" => Ok(OpenMode::Foreground),
+            "bg" => Ok(OpenMode::Background),
+            "term" => Ok(OpenMode::Terminal),
+            _ => HError::log(format!("Unknown open mode: {}", mode))
+        }
+    }
+}
+
+impl Opener {
+    pub fn new() -> Opener {
+        Opener {
+            rules: vec![],
+            modified: None
+        }
+    }
+
+    // Only reads the file again if it changed since the last time
+    pub fn reload(&mut self, path: &Path) -> HResult<()> {
+        let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+        if modified == self.modified { return Ok(()) }
+
+        *self = Opener::load(path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> HResult<Opener> {
+        if !path.exists() {
+            return Ok(Opener::new());
+        }
+
+        let rules = std::fs::read_to_string(path)?
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with("#"))
+            .filter_map(|line| {
+                Rule::parse(line)
+                    .map_err(|_| HError::ConfigLineError(line.to_string()))
+                    .log_and()
+                    .ok()
+            })
+            .collect();
+
+        Ok(Opener {
+            rules: rules,
+            modified: path.metadata().and_then(|meta| meta.modified()).ok()
+        })
+    }
+
+    // All rules matching file, best first
+    pub fn rules_for(&self, file: &File) -> Vec<Rule> {
+        self.rules_with_mime(file, &mut None)
+    }
+
+    // mime is filled in if a rule needs it, so it's only detected once
+    fn rules_with_mime(&self, file: &File, mime: &mut Option<Vec<char>>) -> Vec<Rule> {
+        let name = file.name.chars().collect::<Vec<char>>();
+
+        let mut rules = self.rules
+            .iter()
+            .filter(|rule| rule.matches(file, &name, mime))
+            .cloned()
+            .collect::<Vec<Rule>>();
+
+        rules.extend(Rule::fallback(file));
+        rules
+    }
+
+    pub fn rule_for(&self, file: &File) -> Option<Rule> {
+        self.rules_for(file).into_iter().next()
+    }
+
+    // Everything that can open all files, with a name to show: the
+    // matching rules, then applications declaring the files' mime types
+    pub fn choices_for(&self, files: &[File]) -> Vec<(String, Rule)> {
+        let mimes = files.iter().map(file_mime).collect::<Vec<String>>();
+
+        // Only rules matching every file can open all of them
+        let mut rules: Option<Vec<Rule>> = None;
+        for (file, mime) in files.iter().zip(&mimes) {
+            let file_rules = self.rules_with_mime(file, &mut Some(mime.chars().collect()));
+            rules = Some(match rules {
+                None => file_rules,
+                Some(mut rules) => {
+                    rules.retain(|rule| file_rules.iter().any(|other| {
+                        rule.cmd == other.cmd && rule.mode == other.mode
+                    }));
+                    rules
+                }
+            });
+        }
+
+        let mut choices = rules
+            .unwrap_or(vec![])
+            .into_iter()
+            .map(|rule| (rule.cmd.clone(), rule))
+            .collect::<Vec<_>>();
+        choices.extend(desktop_entries(&mimes));
+
+        // The same command might come from a rule and a desktop entry
+        let mut seen = std::collections::HashSet::new();
+        choices.retain(|(_, rule)| seen.insert(rule.cmd.clone()));
+        choices
+    }
+}
+
+impl Rule {
+    pub fn new(cmd: &str, mode: OpenMode) -> Rule {
+        Rule {
+            matcher: Matcher::Any,
+            mode: mode,
+            cmd: cmd.to_string()
+        }
+    }
+
+    fn parse(line: &str) -> HResult<Rule> {
+        let pos = line.find(" : ").or_else(|| line.find(":\t"))?;
+        let (head, cmd) = (&line[..pos], line[pos+2..].trim());
+        if cmd.is_empty() { return HError::log(format!("No command: {}", line)) }
+
+        let mut head = head.split_whitespace();
+        let matcher = head.next()?;
+        let mode = match head.next() {
+            Some(mode) => mode.parse()?,
+            None => OpenMode::Foreground
+        };
+
+        let matcher = match matcher.find(':') {
+            Some(pos) => {
+                let (kind, pattern) = (&matcher[..pos], &matcher[pos+1..]);
+                match kind {
+                    "mime" => Matcher::Mime(pattern.chars().collect()),
+                    "glob" => Matcher::Glob(pattern.chars().collect()),
+                    "ext" => Matcher::Ext(pattern.split(',')
+                                          .map(|ext| ext.to_lowercase())
+                                          .collect()),
+                    _ => return HError::log(format!("Unknown match: {}", kind))
+                }
+            }
+            None if matcher == "*" => Matcher::Any,
+            None => return HError::log(format!("Unknown match: {}", matcher))
+        };
+
+        Ok(Rule {
+            matcher: matcher,
+            mode: mode,
+            cmd: cmd.to_string()
+        })
+    }
+
+    fn matches(&self, file: &File, name: &[char], mime: &mut Option<Vec<char>>) -> bool {
+        match &self.matcher {
+            Matcher::Any => true,
+            Matcher::Glob(pattern) => glob_match(pattern, name),
+            Matcher::Ext(exts) => {
+                file.path.extension()
+                    .map(|ext| exts.contains(&ext.to_string_lossy().to_lowercase()))
+                    .unwrap_or(false)
+            }
+            Matcher::Mime(pattern) => {
+                let mime = mime.get_or_insert_with(|| file_mime(file).chars().collect());
+                glob_match(pattern, mime)
+            }
+        }
+    }
+
+    // Text goes to $EDITOR, everything else to xdg-open
+    fn fallback(file: &File) -> Vec<Rule> {
+        let mut rules = vec![];
+
+        if let Ok(editor) = std::env::var("EDITOR") {
+            if file.is_text() {
+                rules.push(Rule {
+                    matcher: Matcher::Any,
+                    mode: OpenMode::Foreground,
+                    cmd: format!("{} \"$@\"", editor)
+                });
+            }
+        }
+
+        // xdg-open only takes a single file
+        rules.push(Rule {
+            matcher: Matcher::Any,
+            mode: OpenMode::Foreground,
+            cmd: String::from("for file; do xdg-open \"$file\"; done")
+        });
+
+        rules
+    }
+}
+
+// Usually already detected for the listing, otherwise it's slow
+fn file_mime(file: &File) -> String {
+    file.mime()
+        .cloned()
+        .unwrap_or_else(|| tree_magic::from_filepath(&file.path))
+}
+
+// Applications from the .desktop files in the XDG data directories
+// that declare they can open mime
+fn desktop_entries(mimes: &[String]) -> Vec<(String, Rule)> {
+    let mut dirs = vec![];
+    if let Some(data_dir) = dirs_2::data_dir() {
+        dirs.push(data_dir.join("applications"));
+    }
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .unwrap_or("/usr/local/share:/usr/share".to_string());
+    dirs.extend(data_dirs.split(':').map(|dir| Path::new(dir).join("applications")));
+
+    let mimes = mimes.iter()
+        .map(|mime| mime.chars().collect::<Vec<char>>())
+        .collect::<Vec<_>>();
+
+    dirs.iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|entry| entry.ok()))
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == "desktop").unwrap_or(false))
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .filter_map(|content| DesktopEntry::parse(&content))
+        .filter(|entry| {
+            let patterns = entry.mime_types
+                .iter()
+                .map(|pattern| pattern.chars().collect::<Vec<char>>())
+                .collect::<Vec<_>>();
+            mimes.iter().all(|mime| {
+                patterns.iter().any(|pattern| glob_match(pattern, mime))
+            })
+        })
+        .map(|entry| {
+            let mode = if entry.terminal { OpenMode::Terminal }
+                       else { OpenMode::Background };
+            let rule = Rule::new(&entry.cmd(), mode);
+            (entry.name, rule)
+        })
+        .collect()
+}
+
+struct DesktopEntry {
+    name: String,
+    exec: String,
+    mime_types: Vec<String>,
+    terminal: bool
+}
+
+impl DesktopEntry {
+    fn parse(content: &str) -> Option<DesktopEntry> {
+        let mut name = None;
+        let mut exec = None;
+        let mut mime_types = vec![];
+        let mut terminal = false;
+        let mut in_entry = false;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with("[") {
+                in_entry = line == "[Desktop Entry]";
+                continue;
+            }
+            if !in_entry { continue; }
+
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), value.trim()),
+                _ => continue
+            };
+
+            match key {
+                "Name" => name = Some(value.to_string()),
+                "Exec" => exec = Some(unescape(value)),
+                "MimeType" => {
+                    mime_types = value.split(';')
+                        .filter(|mime| !mime.is_empty())
+                        .map(|mime| mime.to_string())
+                        .collect();
+                }
+                "Terminal" => terminal = value == "true",
+                "NoDisplay" | "Hidden" if value == "true" => return None,
+                _ => {}
+            }
+        }
+
+        Some(DesktopEntry {
+            name: name?,
+            exec: exec?,
+            mime_types: mime_types,
+            terminal: terminal
+        })
+    }
+
+    // Turns the field codes into sh arguments, see the desktop entry spec.
+    // Applications taking a single file are run once for each file, like
+    // xdg-open in Rule::fallback.
+    fn cmd(&self) -> String {
+        let mut has_files = false;
+        let mut single_file = false;
+
+        let cmd = exec_args(&self.exec)
+            .into_iter()
+            .filter_map(|(arg, quoted)| match arg.as_str() {
+                _ if quoted => Some(sh_quote(&arg.replace("%%", "%"))),
+                "%f" | "%u" => { single_file = true; Some("\"$file\"".to_string()) }
+                "%F" | "%U" => { has_files = true; Some("\"$@\"".to_string()) }
+                "%i" | "%c" | "%k" | "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => None,
+                _ => Some(sh_quote(&arg.replace("%%", "%")))
+            })
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        if single_file {
+            format!("for file; do {}; done", cmd)
+        } else if has_files { cmd } else { cmd + " \"$@\"" }
+    }
+}
+
+// The escapes of string values, which come before the quoting in Exec
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next)) => {
+                chars.next();
+                match next {
+                    's' => unescaped.push(' '),
+                    'n' => unescaped.push('\n'),
+                    't' => unescaped.push('\t'),
+                    'r' => unescaped.push('\r'),
+                    '\\' => unescaped.push('\\'),
+                    // Left for the quoting rules of Exec
+                    next => { unescaped.push('\\'); unescaped.push(next); }
+                }
+            }
+            (c, _) => unescaped.push(c)
+        }
+    }
+
+    unescaped
+}
+
+// Splits Exec into arguments, each with whether it was quoted, since
+// field codes only count outside of quotes. Inside double quotes a
+// backslash escapes the next character.
+fn exec_args(exec: &str) -> Vec<(String, bool)> {
+    let mut args = vec![];
+    let mut arg: Option<(String, bool)> = None;
+    let mut in_quotes = false;
+    let mut chars = exec.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                arg.get_or_insert((String::new(), true)).1 = true;
+            }
+            '\\' if in_quotes => {
+                if let Some(next) = chars.next() {
+                    arg.get_or_insert((String::new(), true)).0.push(next);
+                }
+            }
+            ' ' | '\t' if !in_quotes => args.extend(arg.take()),
+            c => arg.get_or_insert((String::new(), false)).0.push(c)
+        }
+    }
+
+    args.extend(arg.take());
+    args
+}
+
+fn sh_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace("'", "'\\''"))
+}
+
+
+// Lists the choices at the bottom of the screen, picked with j/k and
+// Enter or by their number
+pub struct OpenWithPopup {
+    core: WidgetCore,
+    choices: Vec<(String, Rule)>,
+    selection: usize,
+    picked: Option<Rule>
+}
+
+impl OpenWithPopup {
+    pub fn new(core: &WidgetCore, choices: Vec<(String, Rule)>) -> OpenWithPopup {
+        let mut popup = OpenWithPopup {
+            core: core.clone(),
+            choices: choices,
+            selection: 0,
+            picked: None
+        };
+        popup.set_coordinates(&core.coordinates).log();
+        popup
+    }
+
+    pub fn pick(&mut self) -> HResult<Rule> {
+        match self.popup() {
+            Ok(_) | Err(HError::PopupFinnished) => {},
+            err @ Err(_) => err?,
+        }
+        self.clear()?;
+
+        Ok(self.picked.take()?)
+    }
+
+    fn render_line(&self, n: u16, i: usize, name: &str, rule: &Rule) -> String {
+        let xsize = term::xsize();
+        let selected = if i == self.selection { term::invert() } else { String::new() };
+        let line = format!("{}: {}  ({})", i + 1, name, rule.cmd);
+
+        format!("{}{}{}{}",
+                term::goto_xy(1, n),
+                term::reset(),
+                selected,
+                term::sized_string(&line, xsize))
+    }
+}
+
+impl Widget for OpenWithPopup {
+    fn get_core(&self) -> HResult<&WidgetCore> {
+        Ok(&self.core)
+    }
+    fn get_core_mut(&mut self) -> HResult<&mut WidgetCore> {
+        Ok(&mut self.core)
+    }
+    fn refresh(&mut self) -> HResult<()> {
+        Ok(())
+    }
+
+    fn resize(&mut self) -> HResult<()> {
+        HError::terminal_resized()
+    }
+
+    fn set_coordinates(&mut self, _: &Coordinates) -> HResult<()> {
+        let (xsize, ysize) = crate::term::size()?;
+        let len = self.choices.len().min(ysize.saturating_sub(2));
+        let ypos = ysize.saturating_sub(len);
+
+        self.core.coordinates.set_size_u(xsize.saturating_sub(1), len);
+        self.core.coordinates.set_position_u(1, ypos);
+
+        Ok(())
+    }
+
+    fn get_drawlist(&self) -> HResult<String> {
+        let coords = self.get_coordinates()?;
+        let (ypos, ysize) = (coords.ypos(), coords.ysize() as usize);
+        // Keep the selection visible if there are more choices than lines
+        let offset = (self.selection + 1).saturating_sub(ysize);
+
+        Ok(self.choices
+           .iter()
+           .enumerate()
+           .skip(offset)
+           .take(ysize)
+           .map(|(i, (name, rule))| {
+               let line = (i - offset) as u16 + ypos;
+               self.render_line(line, i, name, rule)
+           })
+           .collect())
+    }
+
+    fn on_key(&mut self, key: Key) -> HResult<()> {
+        match key {
+            Key::Ctrl('c') | Key::Esc | Key::Char('q') => {
+                self.picked = None;
+                return HError::popup_finnished()
+            }
+            Key::Char('\n') | Key::Right | Key::Char('l') => {
+                self.picked = self.choices.get(self.selection).map(|(_, rule)| rule.clone());
+                return HError::popup_finnished()
+            }
+            Key::Char(c) if c.is_digit(10) && c != '0' => {
+                let i = c.to_digit(10)? as usize - 1;
+                if let Some((_, rule)) = self.choices.get(i) {
+                    self.picked = Some(rule.clone());
+                    return HError::popup_finnished()
+                }
+            }
+            Key::Up | Key::Char('k') => {
+                self.selection = self.selection.saturating_sub(1);
+            }
+            Key::Down | Key::Char('j') => {
+                if self.selection + 1 < self.choices.len() {
+                    self.selection += 1;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{DesktopEntry, unescape};
+
+    fn cmd(exec: &str) -> String {
+        DesktopEntry {
+            name: String::new(),
+            exec: unescape(exec),
+            mime_types: vec![],
+            terminal: false
+        }.cmd()
+    }
+
+    #[test]
+    fn field_codes() {
+        assert_eq!(cmd("feh %f"), "for file; do 'feh' \"$file\"; done");
+        assert_eq!(cmd("mpv --no-terminal -- %U"), "'mpv' '--no-terminal' '--' \"$@\"");
+        assert_eq!(cmd("gimp %i %F"), "'gimp' \"$@\"");
+        assert_eq!(cmd("vim"), "'vim' \"$@\"");
+    }
+
+    #[test]
+    fn quoted_args() {
+        assert_eq!(cmd("sh -c \"echo 'hi' \\\\$HOME\" %f"),
+                   "for file; do 'sh' '-c' 'echo '\\''hi'\\'' $HOME' \"$file\"; done");
+        assert_eq!(cmd("app \"%f\""), "'app' '%f' \"$@\"");
+        assert_eq!(cmd("app \"\""), "'app' '' \"$@\"");
+    }
+
+    #[test]
+    fn percent_and_escapes() {
+        assert_eq!(cmd("app --progress=100%% %u"),
+                   "for file; do 'app' '--progress=100%' \"$file\"; done");
+        assert_eq!(cmd("app \"a\\sb\""), "'app' 'a b' \"$@\"");
+    }
+}
@@ -2,13 +2,24 @@ use dirs_2;
 
 use std::path::PathBuf;
 
-use crate::fail::HResult;
+use crate::fail::{HResult, HError};
 
 pub fn home_path() -> HResult<PathBuf> {
     let home = dirs_2::home_dir()?;
     Ok(home)
 }
 
+// Replaces a leading ~/ with the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.starts_with("~/"), home_path()) {
+        (true, Ok(mut home)) => {
+            home.push(&path[2..]);
+            home
+        }
+        _ => PathBuf::from(path)
+    }
+}
+
 pub fn ranger_path() -> HResult<PathBuf> {
     let mut ranger_path = dirs_2::config_dir()?;
     ranger_path.push("ranger/");
@@ -22,6 +33,10 @@ pub fn hunter_path() -> HResult<PathBuf> {
 }
 
 pub fn config_path() -> HResult<PathBuf> {
+    if let Some(config_path) = &crate::args::ARGS.config {
+        return Ok(config_path.clone());
+    }
+
     let mut config_path = hunter_path()?;
     config_path.push("config");
     Ok(config_path)
@@ -44,3 +59,90 @@ pub fn history_path() -> HResult<PathBuf> {
     history_path.push("history");
     Ok(history_path)
 }
+
+pub fn session_path(name: &str) -> HResult<PathBuf> {
+    // Plain names only, so sessions stay inside the sessions dir
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-';
+    if name.is_empty() || !name.chars().all(valid) {
+        return HError::log(format!("Invalid session name: {}", name));
+    }
+
+    let mut session_path = hunter_path()?;
+    session_path.push("sessions/");
+    std::fs::create_dir_all(&session_path)?;
+    session_path.push(name);
+    Ok(session_path)
+}
+
+pub fn opener_path() -> HResult<PathBuf> {
+    let mut opener_path = hunter_path()?;
+    opener_path.push("opener");
+    Ok(opener_path)
+}
+
+pub fn scripts_path() -> HResult<PathBuf> {
+    let mut scripts_path = hunter_path()?;
+    scripts_path.push("scripts/");
+    std::fs::create_dir_all(&scripts_path)?;
+    Ok(scripts_path)
+}
+
+pub fn plugins_path() -> HResult<PathBuf> {
+    let mut plugins_path = hunter_path()?;
+    plugins_path.push("plugins/");
+    std::fs::create_dir_all(&plugins_path)?;
+    Ok(plugins_path)
+}
+
+// Sockets for remote control, one per running instance
+pub fn socket_dir() -> HResult<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    let uid = users::get_current_uid();
+    let mut socket_dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir),
+        None => std::env::temp_dir()
+    };
+    socket_dir.push(format!("hunter-{}", uid));
+
+    if !socket_dir.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&socket_dir)?;
+    }
+
+    // In a shared temp dir someone else could have created it first
+    let meta = std::fs::symlink_metadata(&socket_dir)?;
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o777 != 0o700 {
+        return HError::log(format!("Not using {}: it must be a directory owned by you with mode 0700",
+                                   socket_dir.to_string_lossy()));
+    }
+
+    Ok(socket_dir)
+}
+
+pub fn socket_path() -> HResult<PathBuf> {
+    let mut socket_path = socket_dir()?;
+    socket_path.push(format!("{}.sock", std::process::id()));
+    Ok(socket_path)
+}
+
+pub fn dirsettings_path() -> HResult<PathBuf> {
+    let mut dirsettings_path = hunter_path()?;
+    dirsettings_path.push("dirsettings");
+    Ok(dirsettings_path)
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::session_path;
+
+    #[test]
+    fn rejects_session_names_outside_the_sessions_dir() {
+        for name in &["", ".", "..", "../foo", "foo/bar", ".hidden", "foo bar", "~"] {
+            assert!(session_path(name).is_err(), "accepted {:?}", name);
+        }
+    }
+}
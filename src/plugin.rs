@@ -0,0 +1,283 @@
+use serde_json::Value;
+use termion::event::Key;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::files::{File, Kind};
+use crate::filter::glob_match;
+use crate::script::parse_key;
+use crate::fail::{HResult, HError, ErrorLog};
+
+
+lazy_static! {
+    // Plugins show up here once they answered init, so slow ones don't
+    // delay startup or block keys
+    pub static ref PLUGINS: Plugins = Plugins::load();
+}
+
+const PROTOCOL_VERSION: u64 = 1;
+
+// A plugin that takes longer than this to reply is given up on
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Executables in the plugins directory. They talk to hunter with one
+// JSON object per line on stdin/stdout: hunter sends "init", the
+// plugin answers with the actions and previewers it provides. After
+// that each "action" or "preview" request gets exactly one reply.
+pub struct Plugins {
+    plugins: Arc<RwLock<Vec<Arc<Plugin>>>>
+}
+
+pub struct Plugin {
+    name: String,
+    // Separate from io, so it can be killed while a request waits
+    process: Mutex<Child>,
+    io: Mutex<PluginIo>,
+    actions: Vec<Action>,
+    previewers: Vec<PreviewPattern>
+}
+
+struct PluginIo {
+    stdin: ChildStdin,
+    // Lines from stdout, read in a thread so replies can time out
+    replies: Receiver<String>
+}
+
+#[derive(Clone)]
+pub struct Action {
+    pub name: String,
+    key: Option<Key>
+}
+
+enum PreviewPattern {
+    Name(Vec<char>),
+    Mime(Vec<char>)
+}
+
+impl Plugins {
+    // Each plugin starts in its own thread, so one that never answers
+    // doesn't keep the others from loading
+    fn load() -> Plugins {
+        let plugins = Arc::new(RwLock::new(vec![]));
+
+        let paths = crate::paths::plugins_path()
+            .and_then(|path| Ok(std::fs::read_dir(path)?))
+            .map(|entries| {
+                entries.filter_map(|entry| entry.ok())
+                    .map(|entry| entry.path())
+                    .filter(|path| is_executable(path))
+                    .collect()
+            })
+            .unwrap_or(vec![]);
+
+        for path in paths {
+            let plugins = plugins.clone();
+            std::thread::spawn(move || Plugins::add(plugins, path).log());
+        }
+
+        Plugins { plugins: plugins }
+    }
+
+    fn add(plugins: Arc<RwLock<Vec<Arc<Plugin>>>>, path: PathBuf) -> HResult<()> {
+        let plugin = Plugin::start(&path)?;
+        let mut plugins = plugins.write()?;
+        plugins.push(Arc::new(plugin));
+        // Same order as before, no matter which one answered first
+        plugins.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(())
+    }
+
+    pub fn action_for(&self, key: Key) -> Option<(Arc<Plugin>, Action)> {
+        self.plugins
+            .read()
+            .ok()?
+            .iter()
+            .filter_map(|plugin| {
+                plugin.actions
+                    .iter()
+                    .find(|action| action.key == Some(key))
+                    .map(|action| (plugin.clone(), action.clone()))
+            })
+            .next()
+    }
+
+    pub fn previewer_for(&self, file: &File) -> Option<Arc<Plugin>> {
+        if file.kind == Kind::Directory { return None }
+
+        let name = file.name.chars().collect::<Vec<char>>();
+        let mut mime: Option<Vec<char>> = None;
+
+        self.plugins
+            .read()
+            .ok()?
+            .iter()
+            .find(|plugin| {
+                plugin.previewers.iter().any(|pattern| match pattern {
+                    PreviewPattern::Name(pattern) => glob_match(pattern, &name),
+                    PreviewPattern::Mime(pattern) => {
+                        // Only detected if some plugin asks for it
+                        let mime = mime.get_or_insert_with(|| {
+                            tree_magic::from_filepath(&file.path).chars().collect()
+                        });
+                        glob_match(pattern, mime)
+                    }
+                })
+            })
+            .cloned()
+    }
+
+    // Plugins still starting up exit once hunter is gone and their
+    // stdin is closed
+    pub fn stop(&self) {
+        let plugins = match self.plugins.read() {
+            Ok(plugins) => plugins,
+            Err(_) => return
+        };
+
+        for plugin in plugins.iter() {
+            plugin.stop().log();
+        }
+    }
+}
+
+impl Plugin {
+    fn start(path: &Path) -> HResult<Plugin> {
+        let name = path.file_name()?.to_string_lossy().to_string();
+
+        let mut process = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = process.stdin.take()?;
+        let stdout = BufReader::new(process.stdout.take()?);
+        let (tx_reply, rx_reply) = channel();
+
+        // Ends when the plugin exits, which disconnects the channel
+        std::thread::spawn(move || {
+            for line in stdout.lines() {
+                match line {
+                    Ok(line) => if tx_reply.send(line).is_err() { break },
+                    Err(_) => break
+                }
+            }
+        });
+
+        let mut plugin = Plugin {
+            name: name,
+            process: Mutex::new(process),
+            io: Mutex::new(PluginIo {
+                stdin: stdin,
+                replies: rx_reply
+            }),
+            actions: vec![],
+            previewers: vec![]
+        };
+
+        let register = plugin.request(json!({
+            "type": "init",
+            "version": PROTOCOL_VERSION
+        }))?;
+
+        if let Some(actions) = register["actions"].as_array() {
+            plugin.actions = actions
+                .iter()
+                .filter_map(|action| {
+                    Some(Action {
+                        name: action["name"].as_str()?.to_string(),
+                        key: action["key"].as_str().and_then(parse_key)
+                    })
+                })
+                .collect();
+        }
+
+        if let Some(previewers) = register["previewers"].as_array() {
+            plugin.previewers = previewers
+                .iter()
+                .filter_map(|previewer| {
+                    if let Some(pattern) = previewer["pattern"].as_str() {
+                        Some(PreviewPattern::Name(pattern.chars().collect()))
+                    } else if let Some(mime) = previewer["mime"].as_str() {
+                        Some(PreviewPattern::Mime(mime.chars().collect()))
+                    } else { None }
+                })
+                .collect();
+        }
+
+        Ok(plugin)
+    }
+
+    pub fn request(&self, message: Value) -> HResult<Value> {
+        let mut io = self.io.lock()?;
+
+        // Late replies to requests that timed out
+        while io.replies.try_recv().is_ok() {}
+
+        let mut line = message.to_string();
+        line.push('\n');
+        io.stdin.write_all(line.as_bytes())?;
+        io.stdin.flush()?;
+
+        let reply = match io.replies.recv_timeout(REPLY_TIMEOUT) {
+            Ok(reply) => reply,
+            Err(RecvTimeoutError::Timeout) => {
+                return HError::plugin(&self.name, "didn't reply in time");
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return HError::plugin(&self.name, "exited");
+            }
+        };
+
+        let reply: Value = match serde_json::from_str(&reply) {
+            Ok(reply) => reply,
+            Err(err) => {
+                return HError::plugin(&self.name, &format!("sent invalid JSON: {}", err));
+            }
+        };
+        if let Some(err) = reply["error"].as_str() {
+            return HError::plugin(&self.name, err);
+        }
+        Ok(reply)
+    }
+
+    // Kills the process and waits for it, so it doesn't linger as a zombie
+    fn stop(&self) -> HResult<()> {
+        let mut process = self.process.lock()?;
+        // Fails if it already exited, it still has to be waited for
+        process.kill().ok();
+        process.wait()?;
+        Ok(())
+    }
+
+    // context has the cwd and selections, see FileBrowser::plugin_context
+    pub fn run_action(&self, action: &Action, mut context: Value) -> HResult<Value> {
+        context["type"] = json!("action");
+        context["name"] = json!(action.name);
+        self.request(context)
+    }
+
+    // None if the plugin doesn't want to preview the file after all
+    pub fn preview(&self, file: &File, xsize: usize, ysize: usize) -> HResult<Option<String>> {
+        let reply = self.request(json!({
+            "type": "preview",
+            "path": file.path.to_string_lossy(),
+            "width": xsize,
+            "height": ysize
+        }))?;
+
+        Ok(reply["preview"].as_str().map(|preview| preview.to_string()))
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .map(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
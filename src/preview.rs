@@ -6,6 +6,7 @@ use crate::files::{File, Files, Kind};
 use crate::fscache::FsCache;
 use crate::listview::ListView;
 use crate::textview::TextView;
+use crate::imgview::{Graphics, ImageView};
 use crate::widget::{Widget, WidgetCore};
 use crate::coordinates::Coordinates;
 use crate::fail::{HResult, HError, ErrorLog};
@@ -170,6 +171,12 @@ impl<T: Widget + Send + 'static> Widget for AsyncWidget<T> {
         if self.widget().is_err() { return Ok(()) }
         self.widget_mut()?.on_key(key)
     }
+    fn clear(&self) -> HResult<()> {
+        match self.widget() {
+            Ok(widget) => widget.clear(),
+            Err(_) => self.write_to_screen(&self.get_clearlist()?)
+        }
+    }
 }
 
 
@@ -187,7 +194,8 @@ impl PartialEq for Previewer {
 #[derive(PartialEq)]
 enum PreviewWidget {
     FileList(ListView<Files>),
-    TextView(TextView)
+    TextView(TextView),
+    ImageView(ImageView)
 }
 
 
@@ -289,7 +297,10 @@ impl Previewer {
 
     pub fn set_file(&mut self,
                     file: &File) -> HResult<()> {
-        if Some(file) == self.file.as_ref() && !self.widget.is_stale()? { return Ok(()) }
+        let same_line = self.file.as_ref().map(|f| f.line) == Some(file.line);
+        if Some(file) == self.file.as_ref() &&
+            same_line &&
+            !self.widget.is_stale()? { return Ok(()) }
 
         let same_dir = self.file
             .as_ref()
@@ -302,6 +313,7 @@ impl Previewer {
         let core = self.core.clone();
         let cache = self.cache.clone();
         let animator = self.animator.clone();
+        let graphics = self.config().image_preview;
 
         self.widget.set_stale().ok();
 
@@ -315,6 +327,13 @@ impl Previewer {
                                                 move |stale: &Stale| {
             kill_proc().unwrap();
 
+            if let Ok(preview) = Previewer::preview_plugin(&file,
+                                                           &core,
+                                                           &stale,
+                                                           &animator) {
+                return Ok(preview);
+            }
+
             if file.kind == Kind::Directory  {
                 let preview = Previewer::preview_dir(&file,
                                                      cache,
@@ -331,11 +350,28 @@ impl Previewer {
                                                &animator)?);
             }
 
+            if let Some(graphics) = graphics {
+                let preview = Previewer::preview_image(&file,
+                                                       graphics,
+                                                       &core,
+                                                       &stale,
+                                                       &animator);
+                if preview.is_ok() { return preview; }
+                if stale.is_stale()? { return Previewer::preview_failed(&file) }
+            }
+
             let preview = Previewer::preview_external(&file,
                                                       &core,
                                                       &stale,
                                                       &animator);
             if preview.is_ok() { return Ok(preview?); }
+            if stale.is_stale()? { return Previewer::preview_failed(&file) }
+
+            let preview = Previewer::preview_hex(&file,
+                                                 &core,
+                                                 &stale,
+                                                 &animator);
+            if preview.is_ok() { return Ok(preview?); }
             else {
                 let mut blank = TextView::new_blank(&core);
                 blank.set_coordinates(&coordinates).log();
@@ -388,10 +424,29 @@ impl Previewer {
                     animator: &Stale)
                     -> HResult<PreviewWidget> {
         let lines = core.coordinates.ysize() as usize;
-        let mut textview
-            = TextView::new_from_file_limit_lines(&core,
-                                                  &file,
-                                                  lines)?;
+        // Grep results show the matching line in the middle
+        let offset = file.line
+            .map(|line| line.saturating_sub(1 + lines / 2))
+            .unwrap_or(0);
+        let mut textview = TextView::new_blank(&core);
+        let config = textview.config();
+
+        let highlighted = if config.syntax_highlight {
+            crate::highlight::highlight_file(&file,
+                                             lines + offset,
+                                             &config.syntax_theme,
+                                             stale)
+        } else { HError::preview_failed(&file) };
+
+        if stale.is_stale()? { return Previewer::preview_failed(&file) }
+
+        textview.lines = match highlighted {
+            Ok(highlighted) => highlighted,
+            Err(_) => TextView::new_from_file_limit_lines(&core,
+                                                          &file,
+                                                          lines + offset)?.lines
+        };
+        textview.offset = offset;
         if stale.is_stale()? { return Previewer::preview_failed(&file) }
 
         textview.set_coordinates(&core.coordinates)?;
@@ -403,6 +458,90 @@ impl Previewer {
         Ok(PreviewWidget::TextView(textview))
     }
 
+    fn preview_image(file: &File,
+                     graphics: Graphics,
+                     core: &WidgetCore,
+                     stale: &Stale,
+                     animator: &Stale)
+                     -> HResult<PreviewWidget> {
+        if !tree_magic::from_filepath(&file.path).starts_with("image/") {
+            return Previewer::preview_failed(&file)
+        }
+
+        // Without the size of a cell in pixels the image can't be fit
+        // into the column, half blocks don't need it
+        if graphics != Graphics::Unicode && crate::term::cell_size().is_some() {
+            let imageview = ImageView::new(&core, &file, graphics, stale)?;
+            if stale.is_stale()? { return Previewer::preview_failed(&file) }
+            // Not animated: an image is only drawn once it fits, and each
+            // frame would send the whole sixel data again
+            return Ok(PreviewWidget::ImageView(imageview));
+        }
+
+        let (xsize, ysize) = core.coordinates.size_u();
+        let mut textview = TextView {
+            lines: crate::imgview::half_blocks(&file, xsize, ysize, stale)?,
+            core: core.clone(),
+            follow: false,
+            offset: 0};
+        textview.set_coordinates(&core.coordinates)?;
+        textview.refresh()?;
+
+        if stale.is_stale()? { return Previewer::preview_failed(&file) }
+
+        textview.animate_slide_up(Some(animator))?;
+        Ok(PreviewWidget::TextView(textview))
+    }
+
+    fn preview_hex(file: &File,
+                   core: &WidgetCore,
+                   stale: &Stale,
+                   animator: &Stale)
+                   -> HResult<PreviewWidget> {
+        let bytes = crate::hexview::read_at(&file.path, 0, crate::hexview::PREVIEW_SIZE)?;
+        let xsize = core.coordinates.xsize() as usize;
+
+        if stale.is_stale()? { return Previewer::preview_failed(&file) }
+
+        let mut textview = TextView {
+            lines: crate::hexview::hexdump(&bytes, 0, xsize),
+            core: core.clone(),
+            follow: false,
+            offset: 0};
+        textview.set_coordinates(&core.coordinates)?;
+        textview.refresh()?;
+
+        if stale.is_stale()? { return Previewer::preview_failed(&file) }
+
+        textview.animate_slide_up(Some(animator))?;
+        Ok(PreviewWidget::TextView(textview))
+    }
+
+    fn preview_plugin(file: &File,
+                      core: &WidgetCore,
+                      stale: &Stale,
+                      animator: &Stale)
+                      -> HResult<PreviewWidget> {
+        let plugin = crate::plugin::PLUGINS.previewer_for(file)?;
+        let (xsize, ysize) = core.coordinates.size_u();
+        let preview = plugin.preview(file, xsize, ysize)?;
+
+        if stale.is_stale()? { return Previewer::preview_failed(&file) }
+
+        let mut textview = TextView {
+            lines: preview?.lines().map(|line| line.to_string()).collect(),
+            core: core.clone(),
+            follow: false,
+            offset: 0};
+        textview.set_coordinates(&core.coordinates)?;
+        textview.refresh()?;
+
+        if stale.is_stale()? { return Previewer::preview_failed(&file) }
+
+        textview.animate_slide_up(Some(animator))?;
+        Ok(PreviewWidget::TextView(textview))
+    }
+
     fn preview_external(file: &File,
                         core: &WidgetCore,
                         stale: &Stale,
@@ -487,37 +626,53 @@ impl Widget for Previewer {
     fn get_drawlist(&self) -> HResult<String> {
         self.widget.get_drawlist()
     }
+    fn clear(&self) -> HResult<()> {
+        self.widget.clear()
+    }
 }
 
 impl Widget for PreviewWidget {
     fn get_core(&self) -> HResult<&WidgetCore> {
         match self {
             PreviewWidget::FileList(widget) => widget.get_core(),
-            PreviewWidget::TextView(widget) => widget.get_core()
+            PreviewWidget::TextView(widget) => widget.get_core(),
+            PreviewWidget::ImageView(widget) => widget.get_core()
         }
     }
     fn get_core_mut(&mut self) -> HResult<&mut WidgetCore> {
         match self {
             PreviewWidget::FileList(widget) => widget.get_core_mut(),
-            PreviewWidget::TextView(widget) => widget.get_core_mut()
+            PreviewWidget::TextView(widget) => widget.get_core_mut(),
+            PreviewWidget::ImageView(widget) => widget.get_core_mut()
         }
     }
     fn set_coordinates(&mut self, coordinates: &Coordinates) -> HResult<()> {
         match self {
             PreviewWidget::FileList(widget) => widget.set_coordinates(coordinates),
             PreviewWidget::TextView(widget) => widget.set_coordinates(coordinates),
+            PreviewWidget::ImageView(widget) => widget.set_coordinates(coordinates),
         }
     }
     fn refresh(&mut self) -> HResult<()> {
         match self {
             PreviewWidget::FileList(widget) => widget.refresh(),
-            PreviewWidget::TextView(widget) => widget.refresh()
+            PreviewWidget::TextView(widget) => widget.refresh(),
+            PreviewWidget::ImageView(widget) => widget.refresh()
         }
     }
     fn get_drawlist(&self) -> HResult<String> {
         match self {
             PreviewWidget::FileList(widget) => widget.get_drawlist(),
-            PreviewWidget::TextView(widget) => widget.get_drawlist()
+            PreviewWidget::TextView(widget) => widget.get_drawlist(),
+            PreviewWidget::ImageView(widget) => widget.get_drawlist()
+        }
+    }
+
+    fn clear(&self) -> HResult<()> {
+        match self {
+            PreviewWidget::FileList(widget) => widget.clear(),
+            PreviewWidget::TextView(widget) => widget.clear(),
+            PreviewWidget::ImageView(widget) => widget.clear()
         }
     }
 }
@@ -21,6 +21,7 @@ use crate::hbox::HBox;
 use crate::fail::{HResult, HError, ErrorLog};
 use crate::term;
 use crate::files::File;
+use crate::hooks::{Hook, Hooks};
 
 #[derive(Debug)]
 struct Process {
@@ -29,8 +30,8 @@ struct Process {
     output: Arc<Mutex<String>>,
     status: Arc<Mutex<Option<i32>>>,
     success: Arc<Mutex<Option<bool>>>,
-    sender: Sender<Events>
-
+    sender: Sender<Events>,
+    hooks: Hooks
 }
 
 pub struct Cmd {
@@ -44,7 +45,7 @@ pub struct Cmd {
 }
 
 impl Cmd {
-    fn process(&mut self) -> Vec<OsString> {
+    pub fn process(&mut self) -> Vec<OsString> {
         let cmd = self.cmd.clone().split(&OsString::from(" "));
         let cmd = self.substitute_cwd_files(cmd);
         let cmd = self.substitute_tab_files(cmd);
@@ -123,6 +124,7 @@ impl Process {
         let sender = self.sender.clone();
         let cmd = self.cmd.clone();
         let pid = self.handle.lock()?.id();
+        let hooks = self.hooks.clone();
 
         std::thread::spawn(move || -> HResult<()> {
             let stdout = handle.lock()?.stdout.take()?;
@@ -176,6 +178,13 @@ impl Process {
                                      term::normal_color(),
                                      color_status);
                 sender.send(Events::Status(status))?;
+
+                let cwd = File::new_from_path(&std::env::current_dir()?, None)?;
+                hooks.run(Hook::ProcExit,
+                          &cwd,
+                          vec![],
+                          vec![("HUNTER_PROC_CMD", cmd.clone()),
+                               ("HUNTER_PROC_STATUS", proc_status.to_string())]).log();
             }
             Ok(())
         });
@@ -246,7 +255,8 @@ impl ListView<Vec<Process>> {
             output: Arc::new(Mutex::new(String::new())),
             status: Arc::new(Mutex::new(None)),
             success: Arc::new(Mutex::new(None)),
-            sender: self.get_core()?.get_sender()
+            sender: self.get_core()?.get_sender(),
+            hooks: self.config().hooks
         };
         proc.read_proc()?;
         self.content.push(proc);
@@ -589,7 +599,7 @@ impl Widget for ProcView {
 }
 
 
-trait ConcatOsString {
+pub trait ConcatOsString {
     fn concat(&self) -> OsString;
     fn concat_quoted(&self) -> OsString;
 }
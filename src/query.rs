@@ -0,0 +1,342 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::files::File;
+use crate::filter::glob_match;
+use crate::fail::{HResult, HError};
+
+
+// Queries over file attributes, like "size>10M and mtime<2d". Terms
+// are combined with "and" and "or", where "and" binds stronger.
+// Terms next to each other without a keyword are and-ed, too.
+#[derive(Clone)]
+pub struct Query {
+    // Alternatives of terms that all need to match
+    alternatives: Vec<Vec<Term>>,
+    // Filters run on every get_files(), so files without metadata yet
+    // are only looked at once, just like the slow mime detection
+    metas: Arc<RwLock<HashMap<PathBuf, Option<Metadata>>>>,
+    mimes: Arc<RwLock<HashMap<PathBuf, String>>>
+}
+
+#[derive(Clone)]
+enum Term {
+    Size(Ordering, u64),
+    Mtime(Ordering, u64),
+    Type(FileType),
+    Owner(u32),
+    Mime(Vec<char>),
+    Tagged
+}
+
+#[derive(Clone, Copy)]
+enum FileType {
+    Dir,
+    File,
+    Link
+}
+
+impl Query {
+    pub fn parse(source: &str) -> HResult<Query> {
+        let mut alternatives = vec![];
+        let mut terms = vec![];
+
+        for token in source.split_whitespace() {
+            match token {
+                "and" => {},
+                "or" => {
+                    if terms.is_empty() { return query_error(source) }
+                    alternatives.push(std::mem::replace(&mut terms, vec![]));
+                }
+                _ => terms.push(Term::parse(token)?)
+            }
+        }
+
+        if terms.is_empty() { return query_error(source) }
+        alternatives.push(terms);
+
+        Ok(Query {
+            alternatives: alternatives,
+            metas: Arc::new(RwLock::new(HashMap::new())),
+            mimes: Arc::new(RwLock::new(HashMap::new()))
+        })
+    }
+
+    pub fn matches(&self, file: &File) -> bool {
+        // Metadata might not be loaded yet, so get it directly then
+        let meta = file.meta()
+            .ok()
+            .cloned()
+            .or_else(|| self.meta(file));
+
+        self.alternatives
+            .iter()
+            .any(|terms| {
+                terms.iter()
+                    .all(|term| self.term_matches(term, file, meta.as_ref()))
+            })
+    }
+
+    fn term_matches(&self, term: &Term, file: &File, meta: Option<&Metadata>) -> bool {
+        match term {
+            Term::Tagged => file.is_tagged().unwrap_or(false),
+            Term::Mime(pattern) => {
+                let mime = self.mime(file).chars().collect::<Vec<char>>();
+                glob_match(pattern, &mime)
+            }
+            _ => {
+                let meta = match meta {
+                    Some(meta) => meta,
+                    None => return false
+                };
+
+                match term {
+                    Term::Size(ordering, size) => meta.size().cmp(size) == *ordering,
+                    Term::Mtime(ordering, age) => {
+                        let now = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map(|now| now.as_secs() as i64)
+                            .unwrap_or(0);
+                        let file_age = (now - meta.mtime()).max(0) as u64;
+                        file_age.cmp(age) == *ordering
+                    }
+                    Term::Type(FileType::Dir) => meta.file_type().is_dir(),
+                    Term::Type(FileType::File) => meta.file_type().is_file(),
+                    Term::Type(FileType::Link) => meta.file_type().is_symlink(),
+                    Term::Owner(uid) => meta.uid() == *uid,
+                    _ => false
+                }
+            }
+        }
+    }
+
+    fn meta(&self, file: &File) -> Option<Metadata> {
+        if let Some(meta) = self.metas
+            .read()
+            .ok()
+            .and_then(|metas| metas.get(&file.path).cloned()) {
+                return meta;
+            }
+
+        let meta = std::fs::symlink_metadata(&file.path).ok();
+        self.metas
+            .write()
+            .map(|mut metas| metas.insert(file.path.clone(), meta.clone()))
+            .ok();
+        meta
+    }
+
+    fn mime(&self, file: &File) -> String {
+        if let Some(mime) = file.mime() {
+            return mime.clone();
+        }
+
+        if let Some(mime) = self.mimes
+            .read()
+            .ok()
+            .and_then(|mimes| mimes.get(&file.path).cloned()) {
+                return mime;
+            }
+
+        let mime = tree_magic::from_filepath(&file.path);
+        self.mimes
+            .write()
+            .map(|mut mimes| mimes.insert(file.path.clone(), mime.clone()))
+            .ok();
+        mime
+    }
+}
+
+impl Term {
+    fn parse(token: &str) -> HResult<Term> {
+        if token == "tagged" { return Ok(Term::Tagged) }
+
+        if let Some(pos) = token.find(':') {
+            let (key, value) = (&token[..pos], &token[pos+1..]);
+
+            return match key {
+                "type" => {
+                    let kind = match value {
+                        "dir" | "d" => FileType::Dir,
+                        "file" | "f" => FileType::File,
+                        "link" | "l" => FileType::Link,
+                        _ => return query_error(token)
+                    };
+                    Ok(Term::Type(kind))
+                }
+                "owner" => {
+                    let uid = match value.parse::<u32>() {
+                        Ok(uid) => uid,
+                        Err(_) => match users::get_user_by_name(value) {
+                            Some(user) => user.uid(),
+                            None => return query_error(token)
+                        }
+                    };
+                    Ok(Term::Owner(uid))
+                }
+                "mime" => Ok(Term::Mime(value.chars().collect())),
+                _ => query_error(token)
+            }
+        }
+
+        let pos = match token.find(|c| c == '<' || c == '>' || c == '=') {
+            Some(pos) => pos,
+            None => return query_error(token)
+        };
+        let (key, op, value) = (&token[..pos], &token[pos..pos+1], &token[pos+1..]);
+
+        let ordering = match op {
+            "<" => Ordering::Less,
+            ">" => Ordering::Greater,
+            _ => Ordering::Equal
+        };
+
+        match key {
+            "size" => Ok(Term::Size(ordering, parse_number(value, token, &[
+                ('B', 1),
+                ('K', 1024),
+                ('M', 1024 * 1024),
+                ('G', 1024 * 1024 * 1024),
+                ('T', 1024 * 1024 * 1024 * 1024)
+            ])?)),
+            "mtime" => Ok(Term::Mtime(ordering, parse_number(value, token, &[
+                ('s', 1),
+                ('m', 60),
+                ('h', 60 * 60),
+                ('d', 60 * 60 * 24),
+                ('w', 60 * 60 * 24 * 7)
+            ])?)),
+            _ => query_error(token)
+        }
+    }
+}
+
+// Numbers can have a unit suffix, like 10M or 2d
+fn parse_number(value: &str, token: &str, units: &[(char, u64)]) -> HResult<u64> {
+    let (number, multiplier) = match value.chars().last() {
+        Some(suffix) if suffix.is_alphabetic() => {
+            let multiplier = units
+                .iter()
+                .find(|(unit, _)| unit.eq_ignore_ascii_case(&suffix))
+                .map(|(_, multiplier)| *multiplier);
+
+            match multiplier {
+                Some(multiplier) => (&value[..value.len()-suffix.len_utf8()], multiplier),
+                None => return query_error(token)
+            }
+        }
+        _ => (value, 1)
+    };
+
+    match number.parse::<u64>().ok().and_then(|number| number.checked_mul(multiplier)) {
+        Some(number) => Ok(number),
+        None => query_error(token)
+    }
+}
+
+fn query_error<T>(query: &str) -> HResult<T> {
+    Err(HError::FilterError(format!("Can't parse query: {}", query)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Query, Term, FileType, parse_number};
+    use std::cmp::Ordering;
+
+    const SIZES: &[(char, u64)] = &[('K', 1024), ('M', 1024 * 1024)];
+
+    #[test]
+    fn number_without_unit() {
+        assert_eq!(parse_number("42", "size>42", SIZES).unwrap(), 42);
+        assert_eq!(parse_number("0", "size>0", SIZES).unwrap(), 0);
+    }
+
+    #[test]
+    fn number_with_unit() {
+        assert_eq!(parse_number("10M", "size>10M", SIZES).unwrap(), 10 * 1024 * 1024);
+        assert_eq!(parse_number("3k", "size>3k", SIZES).unwrap(), 3 * 1024);
+    }
+
+    #[test]
+    fn number_invalid() {
+        assert!(parse_number("", "size>", SIZES).is_err());
+        assert!(parse_number("M", "size>M", SIZES).is_err());
+        assert!(parse_number("10X", "size>10X", SIZES).is_err());
+        assert!(parse_number("-1", "size>-1", SIZES).is_err());
+        assert!(parse_number("1.5M", "size>1.5M", SIZES).is_err());
+    }
+
+    #[test]
+    fn number_overflow() {
+        let max = u64::max_value().to_string();
+        assert_eq!(parse_number(&max, "size>max", SIZES).unwrap(), u64::max_value());
+        assert!(parse_number(&format!("{}K", max), "size>maxK", SIZES).is_err());
+        assert!(parse_number("99999999999999999999", "size>huge", SIZES).is_err());
+    }
+
+    #[test]
+    fn single_term() {
+        let query = Query::parse("size>10M").unwrap();
+        assert_eq!(query.alternatives.len(), 1);
+
+        match query.alternatives[0][..] {
+            [Term::Size(Ordering::Greater, size)] => assert_eq!(size, 10 * 1024 * 1024),
+            _ => panic!("expected a single size term")
+        }
+    }
+
+    #[test]
+    fn and_terms() {
+        for source in &["size<1K and mtime>2d", "size<1K mtime>2d"] {
+            let query = Query::parse(source).unwrap();
+            assert_eq!(query.alternatives.len(), 1);
+
+            match query.alternatives[0][..] {
+                [Term::Size(Ordering::Less, 1024),
+                 Term::Mtime(Ordering::Greater, age)] => assert_eq!(age, 2 * 60 * 60 * 24),
+                _ => panic!("expected size and mtime terms in {}", source)
+            }
+        }
+    }
+
+    #[test]
+    fn or_binds_weaker_than_and() {
+        let query = Query::parse("type:dir or type:f and tagged").unwrap();
+        assert_eq!(query.alternatives.len(), 2);
+
+        match query.alternatives[0][..] {
+            [Term::Type(FileType::Dir)] => {}
+            _ => panic!("expected type:dir first")
+        }
+        match query.alternatives[1][..] {
+            [Term::Type(FileType::File), Term::Tagged] => {}
+            _ => panic!("expected type:f and tagged second")
+        }
+    }
+
+    #[test]
+    fn other_terms() {
+        let query = Query::parse("owner:0 mime:image/* size=0").unwrap();
+
+        match &query.alternatives[0][..] {
+            [Term::Owner(0), Term::Mime(pattern), Term::Size(Ordering::Equal, 0)] => {
+                assert_eq!(pattern.iter().collect::<String>(), "image/*");
+            }
+            _ => panic!("expected owner, mime and size terms")
+        }
+    }
+
+    #[test]
+    fn invalid_queries() {
+        for source in &["", "or", "size>1 or", "or size>1", "size>1 or or mtime<1",
+                        "size", "size>", "foo>1", "type:x", "color:red",
+                        "size>1Q", "size>99999999999999999999"] {
+            assert!(Query::parse(source).is_err(), "{} should be invalid", source);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use termion::event::Key;
+use serde_json::Value;
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{Sender, channel};
+
+use crate::widget::Events;
+use crate::fail::{HResult, HError, ErrorLog, put_log};
+
+
+// Commands sent by "hunter --remote" or anything else that can write
+// a line to the socket. Each line is answered with one line of JSON.
+#[derive(Debug, Clone)]
+pub enum RemoteCommand {
+    Cd(PathBuf),
+    Select(PathBuf),
+    OpenTab(Option<PathBuf>),
+    Action(String),
+    Cwd,
+    Selection
+}
+
+// Actions run the same code as the key they are bound to
+const ACTIONS: &[(&str, Key)] = &[
+    ("quit", Key::Char('q')),
+    ("quit_with_dir", Key::Char('Q')),
+    ("enter", Key::Char('l')),
+    ("go_back", Key::Char('h')),
+    ("prev_cwd", Key::Char('-')),
+    ("up", Key::Char('k')),
+    ("down", Key::Char('j')),
+    ("top", Key::Char('<')),
+    ("bottom", Key::Char('>')),
+    ("toggle_selection", Key::Char(' ')),
+    ("invert_selection", Key::Char('v')),
+    ("toggle_hidden", Key::Char('H')),
+    ("toggle_columns", Key::Char('c')),
+    ("tree_view", Key::Char('T')),
+    ("flat_view", Key::Char('F')),
+    ("new_tab", Key::Ctrl('t')),
+    ("close_tab", Key::Ctrl('w')),
+    ("next_tab", Key::Char('\t')),
+    ("cycle_sort", Key::Char('s')),
+    ("reverse_sort", Key::Char('r')),
+    ("tag", Key::Char('t')),
+    ("dirs_first", Key::Char('d')),
+];
+
+impl RemoteCommand {
+    pub fn parse(line: &str) -> HResult<RemoteCommand> {
+        let line = line.trim_end_matches(|c| c == '\r' || c == '\n');
+        let mut parts = line.splitn(2, ' ');
+        let (command, arg) = (parts.next().unwrap_or(""), parts.next());
+
+        // Paths are expected to be absolute, the client takes care of that
+        let path = || match arg {
+            Some(path) if !path.is_empty() => Ok(PathBuf::from(path)),
+            _ => HError::remote(&format!("{} needs a path", command))
+        };
+
+        match command {
+            "cd" => Ok(RemoteCommand::Cd(path()?)),
+            "select" => Ok(RemoteCommand::Select(path()?)),
+            "tab" => Ok(RemoteCommand::OpenTab(path().ok())),
+            "action" => Ok(RemoteCommand::Action(arg.unwrap_or("").to_string())),
+            "cwd" => Ok(RemoteCommand::Cwd),
+            "selection" => Ok(RemoteCommand::Selection),
+            _ => HError::remote(&format!("unknown command: {}", command))
+        }
+    }
+
+    pub fn action_key(action: &str) -> HResult<Key> {
+        ACTIONS.iter()
+            .find(|(name, _)| *name == action)
+            .map(|(_, key)| *key)
+            .ok_or(HError::RemoteError(format!("unknown action: {}", action)))
+    }
+}
+
+// Listens on a socket unique to this instance and forwards commands
+// to the main event loop. The path is exported as HUNTER_SOCKET, so
+// subshells and programs started from hunter can find it.
+pub fn start_server(sender: Sender<Events>) -> HResult<PathBuf> {
+    let path = crate::paths::socket_path()?;
+    // Left over from a crashed instance that had the same pid
+    std::fs::remove_file(&path).ok();
+
+    let listener = UnixListener::bind(&path)?;
+    std::env::set_var("HUNTER_SOCKET", &path);
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let sender = sender.clone();
+                    std::thread::spawn(move || {
+                        handle_client(stream, sender).log();
+                    });
+                }
+                Err(err) => { put_log(&HError::from(err)).ok(); }
+            }
+        }
+    });
+
+    Ok(path)
+}
+
+fn handle_client(stream: UnixStream, sender: Sender<Events>) -> HResult<()> {
+    let mut writer = stream.try_clone()?;
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let reply = match RemoteCommand::parse(&line?) {
+            Ok(command) => {
+                let (tx_reply, rx_reply) = channel();
+                sender.send(Events::Remote(command, tx_reply))?;
+                // The reply sender is dropped when a popup has the input
+                rx_reply.recv()
+                    .unwrap_or(error_reply(&HError::RemoteError("hunter is busy".into())))
+            }
+            Err(err) => error_reply(&err)
+        };
+
+        writer.write_all(reply.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+// Client side of --remote, prints the reply and returns whether the
+// command succeeded
+pub fn run_client(socket: Option<&Path>, args: &[String]) -> HResult<bool> {
+    let socket = match socket {
+        Some(socket) => socket.to_path_buf(),
+        None => find_socket()?
+    };
+
+    let mut args = args.to_vec();
+    // Relative paths are relative to the client's cwd, not hunter's
+    if args.len() > 1 && ["cd", "select", "tab"].contains(&args[0].as_str()) {
+        let path = std::env::current_dir()?.join(args[1..].join(" "));
+        args = vec![args[0].clone(), path.to_string_lossy().to_string()];
+    }
+
+    let mut stream = UnixStream::connect(&socket)?;
+    stream.write_all(args.join(" ").as_bytes())?;
+    stream.write_all(b"\n")?;
+
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    print!("{}", reply);
+
+    let reply: Value = serde_json::from_str(&reply)?;
+    Ok(reply.get("error").is_none())
+}
+
+// HUNTER_SOCKET if running inside hunter, otherwise the newest instance
+fn find_socket() -> HResult<PathBuf> {
+    if let Some(socket) = std::env::var_os("HUNTER_SOCKET") {
+        return Ok(PathBuf::from(socket));
+    }
+
+    std::fs::read_dir(crate::paths::socket_dir()?)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map(|ext| ext == "sock").unwrap_or(false))
+        .max_by_key(|path| {
+            path.metadata().and_then(|meta| meta.modified()).ok()
+        })
+        .ok_or(HError::RemoteError("no running hunter found".into()))
+}
+
+pub fn ok_reply() -> String {
+    json!({ "ok": true }).to_string()
+}
+
+pub fn error_reply(err: &HError) -> String {
+    json!({ "error": format!("{}", err) }).to_string()
+}
+
+pub fn cwd_reply(cwd: &Path) -> String {
+    json!({ "cwd": cwd.to_string_lossy() }).to_string()
+}
+
+pub fn selection_reply(selected: Option<&Path>, selections: &[&Path]) -> String {
+    let selections = selections
+        .iter()
+        .map(|path| path.to_string_lossy())
+        .collect::<Vec<_>>();
+
+    json!({
+        "selected": selected.map(|path| path.to_string_lossy()),
+        "selections": selections
+    }).to_string()
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{cwd_reply, error_reply, selection_reply};
+    use crate::fail::HError;
+    use serde_json::Value;
+    use std::path::Path;
+
+    fn parse(reply: &str) -> Value {
+        serde_json::from_str(reply).unwrap()
+    }
+
+    #[test]
+    fn replies_are_json() {
+        let cwd = parse(&cwd_reply(Path::new("/tmp/\"quoted\"\n")));
+        assert_eq!(cwd["cwd"], "/tmp/\"quoted\"\n");
+
+        let selection = parse(&selection_reply(None, &[Path::new("/a b"), Path::new("/c")]));
+        assert!(selection["selected"].is_null());
+        assert_eq!(selection["selections"][0], "/a b");
+
+        let error = parse(&error_reply(&HError::RemoteError("no \\ way".into())));
+        assert!(error.get("error").is_some());
+    }
+}
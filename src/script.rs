@@ -0,0 +1,214 @@
+use rlua::{Lua, Context, Function, RegistryKey, Table};
+use termion::event::Key;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::file_browser::FileBrowser;
+use crate::widget::Widget;
+use crate::fail::{HResult, HError, ErrorLog, put_log};
+
+
+// Functions that need a FileBrowser only exist while a bound function
+// runs, everything else is available when the scripts are loaded
+const BROWSER_API: &[&str] = &[
+    "cwd", "selection", "selected", "goto", "select", "run", "status", "prompt"
+];
+
+// Shared by all browsers. It's empty while a binding runs, so a key
+// press handled from inside the binding can't deadlock.
+pub type SharedScripts = Arc<Mutex<Option<Scripts>>>;
+
+// Lua scripts from the scripts directory, loaded once at startup. They
+// can add keybindings with hunter.bind(key, function).
+pub struct Scripts {
+    lua: Lua,
+    bindings: Arc<Mutex<HashMap<Key, RegistryKey>>>
+}
+
+impl Scripts {
+    pub fn load() -> Scripts {
+        let scripts = Scripts {
+            lua: Lua::new(),
+            bindings: Arc::new(Mutex::new(HashMap::new()))
+        };
+
+        scripts.init().log();
+        scripts.load_scripts().log();
+        scripts
+    }
+
+    pub fn load_shared() -> SharedScripts {
+        Arc::new(Mutex::new(Some(Scripts::load())))
+    }
+
+    fn init(&self) -> HResult<()> {
+        let bindings = self.bindings.clone();
+
+        self.lua.context(|ctx| -> HResult<()> {
+            let hunter = ctx.create_table()?;
+
+            let bind = ctx.create_function(move |ctx, (key, func): (String, Function)| {
+                let key = parse_key(&key)
+                    .ok_or(rlua::Error::RuntimeError(format!("Invalid key: {}", key)))?;
+                // Switching tabs gets it before any browser sees it
+                if key == Key::Char('\t') {
+                    let msg = "Tab switches tabs and can't be bound".to_string();
+                    return Err(rlua::Error::RuntimeError(msg));
+                }
+                let func = ctx.create_registry_value(func)?;
+
+                bindings.lock()
+                    .map(|mut bindings| bindings.insert(key, func))
+                    .map_err(|_| rlua::Error::RuntimeError("Can't bind key".into()))?;
+                Ok(())
+            })?;
+
+            hunter.set("bind", bind)?;
+            ctx.globals().set("hunter", hunter)?;
+            Ok(())
+        })
+    }
+
+    fn load_scripts(&self) -> HResult<()> {
+        let mut paths = std::fs::read_dir(crate::paths::scripts_path()?)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map(|ext| ext == "lua").unwrap_or(false))
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+
+        for path in paths {
+            let name = path.to_string_lossy().to_string();
+            let source = match std::fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(err) => { put_log(&HError::from(err)).ok(); continue; }
+            };
+
+            self.lua.context(|ctx| -> HResult<()> {
+                ctx.load(&source).set_name(&name)?.exec()?;
+                Ok(())
+            }).log();
+        }
+
+        Ok(())
+    }
+
+    // Returns false if nothing is bound to the key
+    pub fn run_binding(&self, key: Key, browser: &mut FileBrowser) -> HResult<bool> {
+        self.lua.context(|ctx| -> HResult<bool> {
+            let func: Function = {
+                let bindings = self.bindings.lock()?;
+                match bindings.get(&key) {
+                    Some(func) => ctx.registry_value(func)?,
+                    None => return Ok(false)
+                }
+            };
+
+            with_browser(ctx, browser, || func.call::<_, ()>(()))?;
+            Ok(true)
+        })
+    }
+}
+
+// Makes the functions in BROWSER_API available while f runs
+fn with_browser<'lua, R>(ctx: Context<'lua>,
+                         browser: &mut FileBrowser,
+                         f: impl FnOnce() -> rlua::Result<R>) -> rlua::Result<R> {
+    let browser = RefCell::new(browser);
+    let hunter: Table = ctx.globals().get("hunter")?;
+
+    let result = ctx.scope(|scope| {
+        hunter.set("cwd", scope.create_function(|_, ()| {
+            Ok(browser.borrow().cwd.path.to_string_lossy().to_string())
+        })?)?;
+
+        hunter.set("selection", scope.create_function(|_, ()| {
+            Ok(browser.borrow()
+               .selected_file()
+               .ok()
+               .map(|file| file.path.to_string_lossy().to_string()))
+        })?)?;
+
+        hunter.set("selected", scope.create_function(|_, ()| {
+            Ok(browser.borrow()
+               .selected_files()
+               .unwrap_or(vec![])
+               .iter()
+               .map(|file| file.path.to_string_lossy().to_string())
+               .collect::<Vec<String>>())
+        })?)?;
+
+        hunter.set("goto", scope.create_function(|_, path: String| {
+            let path = PathBuf::from(path);
+            if !path.is_dir() {
+                let msg = format!("Not a directory: {}", path.to_string_lossy());
+                return Err(rlua::Error::RuntimeError(msg));
+            }
+            browser.borrow_mut().goto_path(&path).map_err(lua_error)
+        })?)?;
+
+        hunter.set("select", scope.create_function(|_, path: String| {
+            browser.borrow_mut().goto_path(&PathBuf::from(path)).map_err(lua_error)
+        })?)?;
+
+        hunter.set("run", scope.create_function(|_, cmd: String| {
+            browser.borrow_mut().run_cmd(&cmd).map_err(lua_error)
+        })?)?;
+
+        hunter.set("status", scope.create_function(|_, status: String| {
+            browser.borrow().show_status(&status).map_err(lua_error)
+        })?)?;
+
+        // nil if the input was cancelled
+        hunter.set("prompt", scope.create_function(|_, query: String| {
+            Ok(browser.borrow().minibuffer(&query).ok())
+        })?)?;
+
+        f()
+    });
+
+    // Scoped functions can't be called anymore after this
+    for name in BROWSER_API {
+        hunter.set(*name, rlua::Nil)?;
+    }
+
+    result
+}
+
+fn lua_error(err: HError) -> rlua::Error {
+    rlua::Error::RuntimeError(format!("{}", err))
+}
+
+// Keys are written like "x", "C-x", "M-x" or "F5"
+pub fn parse_key(key: &str) -> Option<Key> {
+    let single_char = |text: &str| {
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None
+        }
+    };
+
+    if key.starts_with("C-") {
+        return single_char(&key[2..]).map(Key::Ctrl);
+    }
+    if key.starts_with("M-") {
+        return single_char(&key[2..]).map(Key::Alt);
+    }
+    if key.starts_with("F") && key.len() > 1 {
+        if let Ok(num) = key[1..].parse::<u8>() {
+            return Some(Key::F(num));
+        }
+    }
+
+    match key {
+        // Only to reject it in hunter.bind, see Scripts::init
+        "Tab" => Some(Key::Char('\t')),
+        "Enter" => Some(Key::Char('\n')),
+        "Space" => Some(Key::Char(' ')),
+        _ => single_char(key).map(Key::Char)
+    }
+}
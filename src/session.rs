@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+
+use crate::files::File;
+use crate::file_browser::FileBrowser;
+use crate::fscache::FsCache;
+use crate::split::SplitView;
+use crate::tabview::TabView;
+use crate::widget::WidgetCore;
+use crate::fail::{HResult, ErrorLog};
+
+
+// Open tabs with their cwd and selections, saved on quit so they can
+// be restored on the next start. Only the focused browser of each tab
+// is saved, splits aren't restored.
+#[derive(Debug, Clone)]
+pub struct Session {
+    tabs: Vec<TabState>,
+    active: usize,
+}
+
+#[derive(Debug, Clone)]
+struct TabState {
+    cwd: PathBuf,
+    selection: Option<PathBuf>,
+    multi_selections: Vec<PathBuf>,
+}
+
+impl TabState {
+    fn new(cwd: PathBuf) -> TabState {
+        TabState {
+            cwd: cwd,
+            selection: None,
+            multi_selections: vec![]
+        }
+    }
+}
+
+impl Session {
+    pub fn from_tabs(tabview: &TabView<SplitView>) -> Session {
+        let tabs = tabview.widgets
+            .iter()
+            .filter_map(|tab| tab.browser().ok())
+            .map(|browser| {
+                TabState {
+                    cwd: browser.cwd.path.clone(),
+                    selection: browser.selected_file().ok().map(|file| file.path),
+                    multi_selections: browser.selected_files()
+                        .unwrap_or(vec![])
+                        .into_iter()
+                        .map(|file| file.path)
+                        .collect()
+                }
+            })
+            .collect();
+
+        Session {
+            tabs: tabs,
+            active: tabview.active
+        }
+    }
+
+    // Same format as the dirsettings: "key value" on each line
+    pub fn load(name: &str) -> HResult<Session> {
+        let path = crate::paths::session_path(name)?;
+        let content = std::fs::read_to_string(path)?;
+
+        let mut tabs: Vec<TabState> = vec![];
+        let mut active: usize = 0;
+
+        for line in content.lines() {
+            let mut parts = line.splitn(2, " ");
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue
+            };
+
+            match key {
+                "active" => active = value.parse().unwrap_or(0),
+                "tab" => tabs.push(TabState::new(PathBuf::from(value))),
+                "selection" => {
+                    tabs.last_mut().map(|tab| tab.selection = Some(PathBuf::from(value)));
+                }
+                "selected" => {
+                    tabs.last_mut()
+                        .map(|tab| tab.multi_selections.push(PathBuf::from(value)));
+                }
+                _ => {}
+            }
+        }
+
+        // Directories might be gone since the last session, the active
+        // tab moves along with the ones before it
+        let mut kept = vec![];
+        for (i, tab) in tabs.into_iter().enumerate() {
+            if tab.cwd.is_dir() {
+                kept.push(tab);
+            } else if i < active {
+                active -= 1;
+            }
+        }
+
+        Ok(Session {
+            active: active.min(kept.len().saturating_sub(1)),
+            tabs: kept
+        })
+    }
+
+    pub fn save(&self, name: &str) -> HResult<()> {
+        let path = crate::paths::session_path(name)?;
+
+        // Paths with newlines can't be saved in this format
+        let line = |key: &str, path: &Path| {
+            let path = path.to_string_lossy();
+            if path.contains("\n") { String::new() }
+            else { format!("{} {}\n", key, path) }
+        };
+
+        let mut content = format!("active {}\n", self.active);
+
+        for tab in &self.tabs {
+            content += &line("tab", &tab.cwd);
+            if let Some(selection) = &tab.selection {
+                content += &line("selection", selection);
+            }
+            for selected in &tab.multi_selections {
+                content += &line("selected", selected);
+            }
+        }
+
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    pub fn restore(&self, core: &WidgetCore) -> HResult<TabView<SplitView>> {
+        let fs_cache = FsCache::new(core.get_sender());
+        let mut tabview = TabView::new(core);
+        let mut first_browser: Option<FileBrowser> = None;
+
+        for tab in &self.tabs {
+            let cache = match first_browser {
+                None => fs_cache.clone(),
+                Some(_) => {
+                    let settings = fs_cache.tab_settings.read()?.clone();
+                    fs_cache.new_client(settings)?
+                }
+            };
+
+            let cwd = File::new_from_path(&tab.cwd, None)?;
+            let selection = tab.selection
+                .as_ref()
+                .and_then(|path| File::new_from_path(path, None).ok());
+            let multi_selections = tab.multi_selections
+                .iter()
+                .filter_map(|path| File::new_from_path(path, None).ok())
+                .collect();
+            cache.set_selections(&cwd, selection, multi_selections).log();
+
+            let scripts = first_browser.as_ref().map(|browser| browser.scripts());
+            let mut browser = FileBrowser::new_in(core,
+                                                  Some(cache),
+                                                  scripts,
+                                                  tab.cwd.clone())?;
+
+            match &first_browser {
+                Some(first_browser) => {
+                    browser.link_to(first_browser);
+                    tabview.push_widget(SplitView::new(core, browser))?;
+                }
+                None => first_browser = Some(browser)
+            }
+        }
+
+        // The first tab is kept back until the others are linked to it
+        let first_browser = first_browser?;
+        tabview.widgets.insert(0, SplitView::new(core, first_browser));
+        tabview.active = self.active;
+
+        Ok(tabview)
+    }
+}
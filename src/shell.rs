@@ -0,0 +1,213 @@
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use crate::fail::{HResult, HError};
+
+
+// Format of the file written by quit_with_dir, so a wrapper can cd
+// into hunter's last directory after it exits
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExitFormat {
+    // Works for bash and zsh
+    Bash,
+    Fish,
+    // cwd, selected file and the multi selections, each ending in NUL
+    Nul
+}
+
+impl std::str::FromStr for ExitFormat {
+    type Err = HError;
+
+    fn from_str(text: &str) -> HResult<ExitFormat> {
+        match text {
+            "bash" | "zsh" | "sh" => Ok(ExitFormat::Bash),
+            "fish" => Ok(ExitFormat::Fish),
+            "nul" | "null" => Ok(ExitFormat::Nul),
+            _ => HError::log(format!("Unknown exit format: {}", text))
+        }
+    }
+}
+
+impl ExitFormat {
+    pub fn format(&self,
+                  cwd: &Path,
+                  selected_file: &Path,
+                  selected_files: &[&Path]) -> Vec<u8> {
+        match self {
+            ExitFormat::Bash => {
+                let selected_files = selected_files
+                    .iter()
+                    .map(|path| bash_quote(path))
+                    .collect::<Vec<String>>()
+                    .join(" ");
+
+                format!("HUNTER_CWD={}\nF={}\nMF=({})\n",
+                        bash_quote(cwd),
+                        bash_quote(selected_file),
+                        selected_files).into_bytes()
+            }
+            ExitFormat::Fish => {
+                let selected_files = selected_files
+                    .iter()
+                    .map(|path| format!(" {}", fish_quote(path)))
+                    .collect::<String>();
+
+                format!("set -g HUNTER_CWD {}\nset -g F {}\nset -g MF{}\n",
+                        fish_quote(cwd),
+                        fish_quote(selected_file),
+                        selected_files).into_bytes()
+            }
+            ExitFormat::Nul => {
+                let paths = [cwd, selected_file].iter()
+                    .chain(selected_files.iter())
+                    .cloned()
+                    .collect::<Vec<&Path>>();
+
+                join_paths(&paths, b'\0')
+            }
+        }
+    }
+}
+
+// Raw bytes of each path followed by separator, so any file name works
+pub fn join_paths<P: AsRef<Path>>(paths: &[P], separator: u8) -> Vec<u8> {
+    let mut output = vec![];
+    for path in paths {
+        output.extend_from_slice(path.as_ref().as_os_str().as_bytes());
+        output.push(separator);
+    }
+    output
+}
+
+// The exit file usually lives in a shared temp dir, so a symlink put
+// there by someone else isn't followed
+pub fn write_exit_file(path: &Path, content: &[u8]) -> HResult<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    file.write_all(content)?;
+    Ok(())
+}
+
+// Splits bytes into valid UTF-8 text and the invalid bytes in between
+fn utf8_chunks(mut bytes: &[u8]) -> Vec<Result<&str, u8>> {
+    let mut chunks = vec![];
+
+    while !bytes.is_empty() {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => {
+                chunks.push(Ok(text));
+                break;
+            }
+            Err(err) => {
+                let valid = err.valid_up_to();
+                chunks.push(Ok(std::str::from_utf8(&bytes[..valid]).unwrap_or("")));
+
+                let invalid_len = err.error_len().unwrap_or(bytes.len() - valid);
+                for byte in &bytes[valid..valid + invalid_len] {
+                    chunks.push(Err(*byte));
+                }
+                bytes = &bytes[valid + invalid_len..];
+            }
+        }
+    }
+
+    chunks
+}
+
+// ANSI-C quoting as in $'...', which bash and zsh both understand and
+// which can represent any byte
+pub fn bash_quote(path: &Path) -> String {
+    let mut quoted = String::from("$'");
+
+    for chunk in utf8_chunks(path.as_os_str().as_bytes()) {
+        match chunk {
+            Ok(text) => for c in text.chars() {
+                match c {
+                    '\\' => quoted.push_str("\\\\"),
+                    '\'' => quoted.push_str("\\'"),
+                    c if c.is_control() && (c as u32) < 0x80 => {
+                        quoted.push_str(&format!("\\x{:02x}", c as u32));
+                    }
+                    c => quoted.push(c)
+                }
+            }
+            Err(byte) => quoted.push_str(&format!("\\x{:02x}", byte))
+        }
+    }
+
+    quoted.push('\'');
+    quoted
+}
+
+// fish only knows \\ and \' inside single quotes, so bytes that aren't
+// valid UTF-8 are written as \XHH between the quoted parts
+pub fn fish_quote(path: &Path) -> String {
+    let mut quoted = String::from("'");
+
+    for chunk in utf8_chunks(path.as_os_str().as_bytes()) {
+        match chunk {
+            Ok(text) => for c in text.chars() {
+                match c {
+                    '\\' => quoted.push_str("\\\\"),
+                    '\'' => quoted.push_str("\\'"),
+                    c => quoted.push(c)
+                }
+            }
+            Err(byte) => quoted.push_str(&format!("'\\X{:02x}'", byte))
+        }
+    }
+
+    quoted.push('\'');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bash_quote, fish_quote};
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[test]
+    fn bash_quote_plain() {
+        assert_eq!(bash_quote(Path::new("/tmp/a b")), "$'/tmp/a b'");
+    }
+
+    #[test]
+    fn bash_quote_special() {
+        assert_eq!(bash_quote(Path::new("it's\\")), "$'it\\'s\\\\'");
+        assert_eq!(bash_quote(Path::new("a\nb\tc")), "$'a\\x0ab\\x09c'");
+        assert_eq!(bash_quote(Path::new("$(rm)`x`")), "$'$(rm)`x`'");
+    }
+
+    #[test]
+    fn bash_quote_invalid_utf8() {
+        let path = Path::new(OsStr::from_bytes(b"a\xffb"));
+        assert_eq!(bash_quote(path), "$'a\\xffb'");
+    }
+
+    #[test]
+    fn fish_quote_plain() {
+        assert_eq!(fish_quote(Path::new("/tmp/a b")), "'/tmp/a b'");
+        assert_eq!(fish_quote(Path::new("a\nb")), "'a\nb'");
+    }
+
+    #[test]
+    fn fish_quote_special() {
+        assert_eq!(fish_quote(Path::new("it's\\")), "'it\\'s\\\\'");
+        assert_eq!(fish_quote(Path::new("$(rm) (x)")), "'$(rm) (x)'");
+    }
+
+    #[test]
+    fn fish_quote_invalid_utf8() {
+        let path = Path::new(OsStr::from_bytes(b"a\xffb"));
+        assert_eq!(fish_quote(path), "'a'\\Xff'b'");
+    }
+}
@@ -0,0 +1,537 @@
+use termion::event::Key;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::widget::{Widget, WidgetCore};
+use crate::hbox::{HBox, Orientation};
+use crate::file_browser::FileBrowser;
+use crate::proclist::ProcView;
+use crate::textview::TextView;
+use crate::preview::AsyncWidget;
+use crate::coordinates::Coordinates;
+use crate::files::File;
+use crate::remote::RemoteCommand;
+use serde_json::Value;
+use crate::fail::{HResult, HError, ErrorLog};
+
+
+pub enum SplitPane {
+    FileBrowser(FileBrowser),
+    ProcView(Arc<Mutex<ProcView>>, WidgetCore),
+    TextView(AsyncWidget<TextView>, File),
+}
+
+impl Widget for SplitPane {
+    fn get_core(&self) -> HResult<&WidgetCore> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.get_core(),
+            SplitPane::ProcView(_, core) => Ok(core),
+            SplitPane::TextView(widget, _) => widget.get_core(),
+        }
+    }
+    fn get_core_mut(&mut self) -> HResult<&mut WidgetCore> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.get_core_mut(),
+            SplitPane::ProcView(_, core) => Ok(core),
+            SplitPane::TextView(widget, _) => widget.get_core_mut(),
+        }
+    }
+    fn set_coordinates(&mut self, coordinates: &Coordinates) -> HResult<()> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.set_coordinates(coordinates),
+            SplitPane::ProcView(proc_view, core) => {
+                core.coordinates = coordinates.clone();
+                proc_view.lock()?.set_coordinates(coordinates)
+            }
+            SplitPane::TextView(widget, _) => widget.set_coordinates(coordinates),
+        }
+    }
+    fn render_header(&self) -> HResult<String> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.render_header(),
+            SplitPane::ProcView(proc_view, _) => proc_view.lock()?.render_header(),
+            SplitPane::TextView(_, file) => Ok(file.short_string()),
+        }
+    }
+    fn render_footer(&self) -> HResult<String> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.render_footer(),
+            SplitPane::ProcView(proc_view, _) => proc_view.lock()?.render_footer(),
+            SplitPane::TextView(_, _) => Err(HError::NoHeaderError),
+        }
+    }
+    fn refresh(&mut self) -> HResult<()> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.refresh(),
+            SplitPane::ProcView(proc_view, core) => {
+                // The popup and the pane share the same ProcView
+                let mut proc_view = proc_view.lock()?;
+                proc_view.set_coordinates(&core.coordinates).log();
+                proc_view.refresh()
+            }
+            SplitPane::TextView(widget, _) => widget.refresh(),
+        }
+    }
+    fn get_drawlist(&self) -> HResult<String> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.get_drawlist(),
+            SplitPane::ProcView(proc_view, core) => {
+                let mut proc_view = proc_view.lock()?;
+                proc_view.set_coordinates(&core.coordinates).log();
+                proc_view.get_drawlist()
+            }
+            SplitPane::TextView(widget, _) => widget.get_drawlist(),
+        }
+    }
+    fn on_key(&mut self, key: Key) -> HResult<()> {
+        match self {
+            SplitPane::FileBrowser(widget) => widget.on_key(key),
+            SplitPane::ProcView(proc_view, _) => proc_view.lock()?.on_key(key),
+            SplitPane::TextView(widget, _) => {
+                let textview = widget.widget_mut()?;
+                match key {
+                    Key::Up | Key::Char('k') => textview.scroll_up(),
+                    Key::Down | Key::Char('j') => textview.scroll_down(),
+                    Key::PageUp => textview.page_up(),
+                    Key::PageDown => textview.page_down(),
+                    Key::Char('<') => textview.scroll_top(),
+                    Key::Char('>') => textview.scroll_bottom(),
+                    _ => return HError::undefined_key(key)
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+
+// Panes get an id, so they stay apart even with the same coordinates
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub enum SplitNode {
+    Pane(usize, SplitPane),
+    Split(HBox<SplitNode>),
+}
+
+impl PartialEq for SplitNode {
+    fn eq(&self, other: &SplitNode) -> bool {
+        match (self, other) {
+            (SplitNode::Pane(id, _), SplitNode::Pane(other_id, _)) => id == other_id,
+            (SplitNode::Split(hbox), SplitNode::Split(other_hbox)) => hbox == other_hbox,
+            _ => false
+        }
+    }
+}
+
+impl SplitNode {
+    fn pane(pane: SplitPane) -> SplitNode {
+        SplitNode::Pane(NEXT_ID.fetch_add(1, Ordering::Relaxed), pane)
+    }
+
+    fn new_split(core: &WidgetCore, dir: Orientation) -> SplitNode {
+        SplitNode::Split(HBox::with_orientation(core, dir))
+    }
+
+    fn dir(&self) -> Option<Orientation> {
+        match self {
+            SplitNode::Pane(_, _) => None,
+            SplitNode::Split(hbox) => Some(hbox.orientation),
+        }
+    }
+
+    fn children(&self) -> Option<&Vec<SplitNode>> {
+        match self {
+            SplitNode::Pane(_, _) => None,
+            SplitNode::Split(hbox) => Some(&hbox.widgets),
+        }
+    }
+
+    fn children_mut(&mut self) -> Option<&mut Vec<SplitNode>> {
+        match self {
+            SplitNode::Pane(_, _) => None,
+            SplitNode::Split(hbox) => Some(&mut hbox.widgets),
+        }
+    }
+
+    fn active(&self) -> Option<usize> {
+        match self {
+            SplitNode::Pane(_, _) => None,
+            SplitNode::Split(hbox) => hbox.active,
+        }
+    }
+
+    fn set_active(&mut self, i: usize) -> HResult<()> {
+        match self {
+            SplitNode::Pane(_, _) => HError::no_widget(),
+            SplitNode::Split(hbox) => hbox.set_active(i),
+        }
+    }
+
+    fn is_pane(&self) -> bool {
+        match self {
+            SplitNode::Pane(_, _) => true,
+            _ => false
+        }
+    }
+
+    pub fn panes(&self) -> Vec<&SplitPane> {
+        match self {
+            SplitNode::Pane(_, pane) => vec![pane],
+            SplitNode::Split(hbox) => hbox.widgets
+                .iter()
+                .flat_map(|node| node.panes())
+                .collect(),
+        }
+    }
+
+    pub fn panes_mut(&mut self) -> Vec<&mut SplitPane> {
+        match self {
+            SplitNode::Pane(_, pane) => vec![pane],
+            SplitNode::Split(hbox) => hbox.widgets
+                .iter_mut()
+                .flat_map(|node| node.panes_mut())
+                .collect(),
+        }
+    }
+
+    pub fn focused_pane(&self) -> HResult<&SplitPane> {
+        match self {
+            SplitNode::Pane(_, pane) => Ok(pane),
+            _ => self.children()?.get(self.active()?)?.focused_pane()
+        }
+    }
+
+    pub fn focused_pane_mut(&mut self) -> HResult<&mut SplitPane> {
+        match self {
+            SplitNode::Pane(_, pane) => Ok(pane),
+            _ => {
+                let active = self.active()?;
+                self.children_mut()?.get_mut(active)?.focused_pane_mut()
+            }
+        }
+    }
+
+    // Position of the focused pane when walking the tree depth first
+    fn focused_index(&self) -> usize {
+        let (children, active) = match (self.children(), self.active()) {
+            (Some(children), Some(active)) => (children, active),
+            _ => return 0
+        };
+
+        let before = children.iter()
+            .take(active)
+            .map(|node| node.panes().len())
+            .sum::<usize>();
+
+        before + children.get(active)
+            .map(|node| node.focused_index())
+            .unwrap_or(0)
+    }
+
+    fn focus_index(&mut self, index: usize) -> HResult<()> {
+        if self.is_pane() { return Ok(()) }
+
+        let pane_counts = self.children()?
+            .iter()
+            .map(|node| node.panes().len())
+            .collect::<Vec<usize>>();
+
+        let mut index = index;
+        for (i, count) in pane_counts.into_iter().enumerate() {
+            if index < count {
+                self.set_active(i)?;
+                return self.children_mut()?[i].focus_index(index);
+            }
+            index -= count;
+        }
+        HError::no_widget()
+    }
+
+    fn split_focused(&mut self,
+                     core: &WidgetCore,
+                     pane: SplitPane,
+                     dir: Orientation) -> HResult<()> {
+        let active = self.active()?;
+
+        if !self.children()?.get(active)?.is_pane() {
+            return self.children_mut()?[active].split_focused(core, pane, dir);
+        }
+
+        if self.dir() == Some(dir) {
+            self.children_mut()?.insert(active + 1, SplitNode::pane(pane));
+            self.set_active(active + 1)?;
+            return Ok(());
+        }
+
+        let old_pane = self.children_mut()?.remove(active);
+        let mut split = SplitNode::new_split(core, dir);
+        split.children_mut()?.push(old_pane);
+        split.children_mut()?.push(SplitNode::pane(pane));
+        split.set_active(1)?;
+
+        if self.children()?.is_empty() {
+            // Only had one pane, so just change direction
+            *self = split;
+        } else {
+            self.children_mut()?.insert(active, split);
+        }
+        Ok(())
+    }
+
+    fn close_focused(&mut self) -> HResult<()> {
+        let active = self.active()?;
+
+        if !self.children()?.get(active)?.is_pane() {
+            self.children_mut()?[active].close_focused()?;
+
+            // Don't keep splits around that only hold a single node
+            let single_child = self.children()?[active]
+                .children()
+                .map(|children| children.len() == 1)
+                .unwrap_or(false);
+
+            if single_child {
+                let node = self.children_mut()?[active].children_mut()?.remove(0);
+                self.children_mut()?[active] = node;
+            }
+            return Ok(());
+        }
+
+        if self.children()?.len() == 1 { return HError::no_widget() }
+
+        self.children_mut()?.remove(active);
+        self.set_active(active.saturating_sub(1))?;
+        Ok(())
+    }
+}
+
+impl Widget for SplitNode {
+    fn get_core(&self) -> HResult<&WidgetCore> {
+        match self {
+            SplitNode::Pane(_, widget) => widget.get_core(),
+            SplitNode::Split(widget) => widget.get_core(),
+        }
+    }
+    fn get_core_mut(&mut self) -> HResult<&mut WidgetCore> {
+        match self {
+            SplitNode::Pane(_, widget) => widget.get_core_mut(),
+            SplitNode::Split(widget) => widget.get_core_mut(),
+        }
+    }
+    fn set_coordinates(&mut self, coordinates: &Coordinates) -> HResult<()> {
+        match self {
+            SplitNode::Pane(_, widget) => widget.set_coordinates(coordinates),
+            SplitNode::Split(widget) => widget.set_coordinates(coordinates),
+        }
+    }
+    fn refresh(&mut self) -> HResult<()> {
+        match self {
+            SplitNode::Pane(_, widget) => widget.refresh(),
+            SplitNode::Split(widget) => widget.refresh(),
+        }
+    }
+    fn get_drawlist(&self) -> HResult<String> {
+        match self {
+            SplitNode::Pane(_, widget) => widget.get_drawlist(),
+            SplitNode::Split(widget) => widget.get_drawlist(),
+        }
+    }
+}
+
+
+pub struct SplitView {
+    core: WidgetCore,
+    root: SplitNode,
+}
+
+impl SplitView {
+    pub fn new(core: &WidgetCore, browser: FileBrowser) -> SplitView {
+        // The root is always a split, so every pane has a parent
+        let mut root = HBox::new(core);
+        root.push_widget(SplitNode::pane(SplitPane::FileBrowser(browser)));
+        root.set_active(0).log();
+
+        SplitView {
+            core: core.clone(),
+            root: SplitNode::Split(root)
+        }
+    }
+
+    pub fn browsers(&self) -> Vec<&FileBrowser> {
+        self.root
+            .panes()
+            .into_iter()
+            .filter_map(|pane| match pane {
+                SplitPane::FileBrowser(browser) => Some(browser),
+                _ => None
+            }).collect()
+    }
+
+    pub fn browsers_mut(&mut self) -> Vec<&mut FileBrowser> {
+        self.root
+            .panes_mut()
+            .into_iter()
+            .filter_map(|pane| match pane {
+                SplitPane::FileBrowser(browser) => Some(browser),
+                _ => None
+            }).collect()
+    }
+
+    // The focused FileBrowser, or the first one if another pane has focus
+    pub fn browser(&self) -> HResult<&FileBrowser> {
+        if let SplitPane::FileBrowser(browser) = self.root.focused_pane()? {
+            return Ok(browser);
+        }
+        Ok(self.browsers().into_iter().next()?)
+    }
+
+    pub fn browser_mut(&mut self) -> HResult<&mut FileBrowser> {
+        let focused_browser = match self.root.focused_pane()? {
+            SplitPane::FileBrowser(_) => true,
+            _ => false
+        };
+
+        if !focused_browser {
+            return Ok(self.browsers_mut().into_iter().next()?);
+        }
+
+        match self.root.focused_pane_mut()? {
+            SplitPane::FileBrowser(browser) => Ok(browser),
+            _ => HError::no_widget()
+        }
+    }
+
+    pub fn split(&mut self, pane: SplitPane, dir: Orientation) -> HResult<()> {
+        let core = self.core.clone();
+        self.root.split_focused(&core, pane, dir)?;
+        self.clear().log();
+        self.refresh()
+    }
+
+    pub fn split_browser(&mut self, dir: Orientation) -> HResult<()> {
+        let browser = self.browser()?.new_linked(&self.core)?;
+        self.split(SplitPane::FileBrowser(browser), dir)
+    }
+
+    pub fn split_procview(&mut self, dir: Orientation) -> HResult<()> {
+        let proc_view = self.browser()?.proc_view();
+        let pane = SplitPane::ProcView(proc_view, self.core.clone());
+        self.split(pane, dir)
+    }
+
+    pub fn split_textview(&mut self, dir: Orientation) -> HResult<()> {
+        let file = self.browser()?.selected_file()?;
+
+        if file.is_dir() {
+            return self.show_status("Can't view a directory!");
+        }
+
+        let core = self.core.clone();
+        let pane_file = file.clone();
+        let textview = AsyncWidget::new(&self.core, move |_| {
+            TextView::new_from_file(&core, &pane_file)
+        });
+
+        self.split(SplitPane::TextView(textview, file), dir)
+    }
+
+    pub fn close_pane(&mut self) -> HResult<()> {
+        let closing_browser = match self.root.focused_pane()? {
+            SplitPane::FileBrowser(_) => true,
+            _ => false
+        };
+
+        if closing_browser && self.browsers().len() == 1 {
+            return self.show_status("Can't close the last file browser!");
+        }
+
+        match self.root.close_focused() {
+            Err(HError::NoWidgetError) => self.show_status("Can't close the last pane!")?,
+            result => result?
+        }
+
+        self.clear().log();
+        self.refresh()
+    }
+
+    pub fn focus_next(&mut self) -> HResult<()> {
+        let panes = self.root.panes().len();
+        let next = (self.root.focused_index() + 1) % panes;
+        self.root.focus_index(next)
+    }
+
+    pub fn focus_prev(&mut self) -> HResult<()> {
+        let panes = self.root.panes().len();
+        let prev = (self.root.focused_index() + panes - 1) % panes;
+        self.root.focus_index(prev)
+    }
+}
+
+impl Widget for SplitView {
+    fn get_core(&self) -> HResult<&WidgetCore> {
+        Ok(&self.core)
+    }
+    fn get_core_mut(&mut self) -> HResult<&mut WidgetCore> {
+        Ok(&mut self.core)
+    }
+
+    fn set_coordinates(&mut self, coordinates: &Coordinates) -> HResult<()> {
+        self.core.coordinates = coordinates.clone();
+        self.root.set_coordinates(coordinates)
+    }
+
+    fn render_header(&self) -> HResult<String> {
+        self.root.focused_pane()?.render_header()
+    }
+
+    fn render_footer(&self) -> HResult<String> {
+        self.root.focused_pane()?.render_footer()
+    }
+
+    fn refresh(&mut self) -> HResult<()> {
+        self.root.refresh().log();
+
+        // Every FileBrowser sets the cwd/title, so the focused one goes last
+        if let SplitPane::FileBrowser(browser) = self.root.focused_pane_mut()? {
+            browser.set_cwd().log();
+            browser.set_title().log();
+        }
+        Ok(())
+    }
+
+    fn get_drawlist(&self) -> HResult<String> {
+        self.root.get_drawlist()
+    }
+
+    fn on_remote(&mut self, command: RemoteCommand) -> HResult<String> {
+        self.browser_mut()?.on_remote(command)
+    }
+
+    fn on_plugin_reply(&mut self, reply: HResult<Value>) -> HResult<()> {
+        self.browser_mut()?.on_plugin_reply(reply)
+    }
+
+    fn on_key(&mut self, key: Key) -> HResult<()> {
+        match key {
+            Key::Alt('|') => self.split_browser(Orientation::Horizontal),
+            Key::Alt('-') => self.split_browser(Orientation::Vertical),
+            Key::Alt('w') => self.split_procview(Orientation::Vertical),
+            Key::Alt('t') => self.split_textview(Orientation::Horizontal),
+            Key::Alt('x') => self.close_pane(),
+            Key::Alt('o') => self.focus_next(),
+            Key::Alt('O') => self.focus_prev(),
+            _ => match self.root.focused_pane_mut()?.on_key(key) {
+                // Panes closing themselves, like the ProcView with "w"
+                Err(HError::PopupFinnished) => self.close_pane(),
+                result => result
+            }
+        }
+    }
+}
+
+impl PartialEq for SplitView {
+    fn eq(&self, other: &SplitView) -> bool {
+        self.root == other.root
+    }
+}
@@ -1,6 +1,8 @@
 use termion::event::Key;
 
 use crate::widget::{Widget, WidgetCore};
+use crate::remote::RemoteCommand;
+use serde_json::Value;
 use crate::fail::{HResult, ErrorLog};
 use crate::coordinates::Coordinates;
 
@@ -27,6 +29,10 @@ pub trait Tabbable {
     }
     fn on_refresh(&mut self) -> HResult<()> { Ok(()) }
     fn on_config_loaded(&mut self) -> HResult<()> { Ok(()) }
+    fn on_quit(&mut self) -> HResult<()> { Ok(()) }
+    fn on_remote(&mut self, command: RemoteCommand) -> HResult<String> {
+        self.active_tab_mut().on_remote(command)
+    }
 
 
 }
@@ -115,6 +121,14 @@ impl<T> Widget for TabView<T> where T: Widget, TabView<T>: Tabbable {
         self.on_config_loaded()
     }
 
+    fn on_remote(&mut self, command: RemoteCommand) -> HResult<String> {
+        Tabbable::on_remote(self, command)
+    }
+
+    fn on_plugin_reply(&mut self, reply: HResult<Value>) -> HResult<()> {
+        self.active_tab_mut().on_plugin_reply(reply)
+    }
+
     fn set_coordinates(&mut self, coordinates: &Coordinates) -> HResult<()> {
         self.core.coordinates = coordinates.clone();
         for widget in &mut self.widgets {
@@ -181,6 +181,25 @@ pub fn flush_stdin() {
     stdin.consume(10);
 }
 
+pub fn is_kitty() -> bool {
+    std::env::var("TERM").map(|term| term == "xterm-kitty").unwrap_or(false) ||
+        std::env::var_os("KITTY_WINDOW_ID").is_some()
+}
+
+// Size of a character cell in pixels, if the terminal reports it
+pub fn cell_size() -> Option<(usize, usize)> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size as *mut _) };
+
+    if ret != 0 || size.ws_xpixel == 0 || size.ws_ypixel == 0 ||
+        size.ws_col == 0 || size.ws_row == 0 {
+        return None;
+    }
+
+    Some(((size.ws_xpixel / size.ws_col) as usize,
+          (size.ws_ypixel / size.ws_row) as usize))
+}
+
 pub fn xsize() -> u16 {
     let (xsize, _) = termion::terminal_size().unwrap();
     xsize
@@ -212,6 +231,30 @@ pub fn sized_string(string: &str, xsize: u16) -> String {
     })
 }
 
+// Like sized_string, but escape sequences don't count towards the width
+// and are never cut in half
+pub fn sized_string_ansi(string: &str, xsize: u16) -> String {
+    let ansi_pos = parse_bytes(string.as_bytes())
+        .map(|m| (m.start(), m.end()))
+        .collect::<Vec<(usize, usize)>>();
+    let mut width = 0;
+    let mut sized = String::with_capacity(string.len());
+
+    for (pos, ch) in string.char_indices() {
+        if ansi_pos.iter().any(|(start, end)| pos >= *start && pos < *end) {
+            sized.push(ch);
+            continue;
+        }
+
+        let ch_width = unicode_width::UnicodeWidthChar::width_cjk(ch).unwrap_or(0);
+        if width + ch_width >= xsize as usize { break }
+        width += ch_width;
+        sized.push(ch);
+    }
+
+    sized
+}
+
 fn is_ansi(ansi_pos: &Vec<(usize, usize)>, char_pos: &usize) -> bool {
     ansi_pos.iter().fold(false, |is_ansi, (start, end)| {
         if char_pos >= start && char_pos <= end {
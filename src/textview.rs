@@ -1,7 +1,7 @@
 use std::io::BufRead;
 
 use crate::files::File;
-use crate::term::sized_string;
+use crate::term::sized_string_ansi;
 use crate::widget::{Widget, WidgetCore};
 use crate::fail::HResult;
 use crate::dirty::Dirtyable;
@@ -161,10 +161,11 @@ impl Widget for TextView {
             .enumerate()
             .map(|(i, line)| {
                 format!(
-                    "{}{}{}",
+                    "{}{}{}{}",
                     crate::term::goto_xy(xpos, i as u16 + ypos),
                     crate::term::reset(),
-                    sized_string(&line, xsize))
+                    sized_string_ansi(&line, xsize),
+                    crate::term::reset())
             })
             .collect::<String>();
         Ok(output)
@@ -5,6 +5,7 @@ use std::io::{Write, stdin};
 use termion::event::{Event, Key, MouseEvent};
 use termion::input::TermRead;
 use async_value::{Async, Stale};
+use serde_json::Value;
 
 
 use crate::coordinates::{Coordinates, Position, Size};
@@ -15,6 +16,7 @@ use crate::term::{Screen, ScreenExt};
 use crate::dirty::{Dirtyable, DirtyBit};
 use crate::signal_notify::{notify, Signal};
 use crate::config::Config;
+use crate::remote::RemoteCommand;
 
 
 
@@ -29,6 +31,8 @@ pub enum Events {
     RequestInput,
     Status(String),
     ConfigLoaded,
+    Remote(RemoteCommand, Sender<String>),
+    PluginReply(HResult<Value>),
 }
 
 impl PartialEq for WidgetCore {
@@ -145,6 +149,12 @@ pub trait Widget {
     fn get_drawlist(&self) -> HResult<String>;
     fn after_draw(&self) -> HResult<()> { Ok(()) }
     fn config_loaded(&mut self) -> HResult<()> { Ok(()) }
+    fn on_remote(&mut self, _command: RemoteCommand) -> HResult<String> {
+        HError::remote("not supported here")
+    }
+    fn on_plugin_reply(&mut self, reply: HResult<Value>) -> HResult<()> {
+        reply.map(|_| ())
+    }
 
 
 
@@ -272,6 +282,7 @@ pub trait Widget {
                         err @ Err(HError::Quit) |
                         err @ Err(HError::MiniBufferCancelledInput) => err?,
                         err @ Err(HError::MiniBufferInputUpdated(_)) => err?,
+                        err @ Err(HError::MiniBufferSelectionMoved(_)) => err?,
                         err @ Err(HError::WidgetResizedError) => err?,
                         err @ Err(_) => err.log(),
                         Ok(_) => {}
@@ -401,6 +412,21 @@ pub trait Widget {
                     self.get_core_mut()?.config.write()?.pull_async()?;
                     self.config_loaded().log();
                 }
+                Events::Remote(command, reply) => {
+                    match self.on_remote(command) {
+                        Ok(response) => { reply.send(response).ok(); }
+                        Err(HError::Quit) => {
+                            reply.send(crate::remote::ok_reply()).ok();
+                            HError::quit()?;
+                        }
+                        Err(err) => {
+                            reply.send(crate::remote::error_reply(&err)).ok();
+                        }
+                    }
+                }
+                Events::PluginReply(reply) => {
+                    self.on_plugin_reply(reply).log();
+                }
                 _ => {}
             }
             self.resize().log();
@@ -471,6 +497,19 @@ pub trait Widget {
         answer
     }
 
+    // Like minibuffer_continuous, but Up/Down and C-p/C-n move a
+    // selection in the caller's results instead of going through history
+    fn minibuffer_selection(&self, query: &str) -> HResult<String> {
+        let answer = self.get_core()?
+            .minibuffer
+            .lock()?
+            .as_mut()?
+            .query_selection(query);
+        let mut screen = self.screen()?;
+        screen.cursor_hide().log();
+        answer
+    }
+
     fn screen(&self) -> HResult<Screen> {
         Ok(self.get_core()?.screen.clone())
     }